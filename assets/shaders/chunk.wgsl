@@ -0,0 +1,59 @@
+#import bevy_pbr::{
+    mesh_functions::{get_model_matrix, mesh_position_local_to_world},
+    mesh_view_bindings::{view, fog},
+    view_transformations::position_world_to_clip,
+    pbr_functions::apply_fog,
+}
+
+@group(2) @binding(0) var atlas_texture: texture_2d<f32>;
+@group(2) @binding(1) var atlas_sampler: sampler;
+@group(2) @binding(2) var<uniform> daylight: f32;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) tile: vec4<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) tint: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) tile: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) world_position: vec4<f32>,
+    @location(4) tint: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_position = mesh_position_local_to_world(
+        get_model_matrix(vertex.instance_index),
+        vec4<f32>(vertex.position, 1.0),
+    );
+    out.clip_position = position_world_to_clip(out.world_position.xyz);
+    out.uv = vertex.uv;
+    out.tile = vertex.tile;
+    out.color = vertex.color;
+    out.tint = vertex.tint;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Wrap the uvs inside the block's atlas tile, so merged faces repeat the texture
+    // instead of stretching it. Gradients come from the unwrapped uvs to avoid seams.
+    let uv = in.tile.xy + fract(in.uv) * in.tile.zw;
+    let color = textureSampleGrad(atlas_texture, atlas_sampler, uv, dpdx(in.uv) * in.tile.zw, dpdy(in.uv) * in.tile.zw);
+
+    // Vertex colours hold AO in red and the sky and block light levels in green and blue.
+    // Every level of light lost darkens the face by a fifth, down to a little ambient light.
+    let light = max(in.color.g * daylight, in.color.b);
+    let brightness = max(pow(0.8, (1.0 - light) * 15.0), 0.05);
+
+    let lit = vec4<f32>(color.rgb * in.tint * in.color.r * brightness, color.a);
+    return apply_fog(fog, lit, in.world_position.xyz, view.world_position.xyz);
+}
@@ -1,6 +1,7 @@
 use bevy::{prelude::*, window::{PresentMode, WindowResolution}};
 use bevy_rapier3d::prelude::{RapierPhysicsPlugin, NoUserData};
-use plugins::{camera::CameraPlugin, menu::MenuPlugin, player::PlayerPlugin, world::WorldPlugin};
+use plugins::world::chunk::preset::DEFAULT_LAYERS;
+use plugins::{camera::CameraPlugin, menu::{MenuPlugin, CreateWorldInput}, player::PlayerPlugin, world::WorldPlugin};
 
 
 mod plugins;
@@ -8,6 +9,9 @@ mod plugins;
 pub const CHUNK_WIDTH: usize = 8;
 pub const CHUNK_HEIGHT: usize = 256;
 pub const CHUNK_VOL: usize = CHUNK_WIDTH * CHUNK_WIDTH * CHUNK_HEIGHT;
+pub const SECTION_HEIGHT: usize = 16;
+pub const SECTION_COUNT: usize = CHUNK_HEIGHT / SECTION_HEIGHT;
+pub const SECTION_VOL: usize = CHUNK_WIDTH * CHUNK_WIDTH * SECTION_HEIGHT;
 pub const RENDER_DISTANCE: i32 = 24;
 
 
@@ -39,6 +43,12 @@ fn main() {
         .add_plugins(PlayerPlugin)
         .add_plugins(WorldPlugin)
         .add_plugins(MenuPlugin)
+        .insert_resource(CreateWorldInput {
+            seed: argument("--seed").unwrap_or_default(),
+            world_type: argument("--world-type").unwrap_or_else(|| "default".to_string()),
+            layers: argument("--layers").unwrap_or_else(|| DEFAULT_LAYERS.to_string()),
+            ..default()
+        })
         .add_systems(Update, globalkeys)
         .run();
 }
@@ -51,6 +61,23 @@ fn globalkeys(keyboard: Res<ButtonInput<KeyCode>>, mut gamestate: ResMut<NextSta
 }
 
 
+// Options of a new world can be given on the command line, like --seed <seed> or --seed=<seed>.
+fn argument(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+
+    None
+}
+
+
 // Cleaup tag for game stuff.
 #[derive(Component)]
 struct GameGarbage;
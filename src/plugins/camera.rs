@@ -1,6 +1,12 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, pbr::{FogSettings, FogFalloff}};
 
+use crate::{GameState, CHUNK_WIDTH, RENDER_DISTANCE};
 use crate::plugins::player::components::PlayerCamera;
+use crate::plugins::world::{WorldMap, WATER_COLOR, chunk::fluid::is_water, time::update_sky};
+
+
+// Underwater everything fades into the water colour within a few blocks.
+const UNDERWATER_FOG_DISTANCE: f32 = 16.0;
 
 
 pub struct CameraPlugin;
@@ -9,7 +15,8 @@ impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app
             //.add_startup_system(setup_camera_system)
-            .add_systems(Startup, (setup_light_system, setup_camera_system));
+            .add_systems(Startup, (setup_light_system, setup_camera_system))
+            .add_systems(Update, update_fog.after(update_sky).run_if(in_state(GameState::Running)));
     }
 }
 
@@ -27,10 +34,46 @@ pub fn setup_camera_system(mut commands: Commands) {
         }),
         ..default()
     }))
+    .insert(fog_settings(Color::hex("8fd3ff").unwrap()))
     .insert(PlayerCamera::default());
 }
 
 
+// Fog that thickens towards the edge of the loaded chunks, so they fade in and out
+// instead of popping. Chunks are loaded RENDER_DISTANCE chunks out from the player's.
+fn fog_settings(color: Color) -> FogSettings {
+    let end = (RENDER_DISTANCE - 1) as f32 * CHUNK_WIDTH as f32;
+
+    FogSettings {
+        color,
+        falloff: FogFalloff::Linear { start: end * 0.6, end },
+        ..default()
+    }
+}
+
+
+pub fn update_fog(
+    mut camera_query: Query<(&Transform, &mut FogSettings), With<PlayerCamera>>,
+    clear_color: Res<ClearColor>,
+    world_map: Res<WorldMap>,
+) {
+    let Ok((transform, mut fog)) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    if world_map.block_at(transform.translation).is_some_and(is_water) {
+        // Darken the water with the sky, so it doesn't glow at night.
+        let sky = clear_color.0.l();
+        let [r, g, b, _] = WATER_COLOR;
+        fog.color = Color::rgb(r, g, b) * (0.2 + sky);
+        fog.falloff = FogFalloff::Linear { start: 0.0, end: UNDERWATER_FOG_DISTANCE };
+    }
+    else {
+        *fog = fog_settings(clear_color.0);
+    }
+}
+
+
 pub fn setup_light_system(mut commands: Commands) {
     commands.insert_resource(ClearColor(Color::hex("8fd3ff").unwrap()));
 }
@@ -1,5 +1,6 @@
 use bevy::{prelude::*, app::AppExit };
 use crate::GameState;
+use crate::plugins::world::{NewWorld, chunk::{seeding::parse_seed, preset::{WorldPreset, DEFAULT_LAYERS}}, storage::WorldStorage};
 
 
 const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
@@ -7,33 +8,139 @@ const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.25, 0.65, 0.25);
 const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+const HINT_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+const ERROR_COLOR: Color = Color::rgb(1.0, 0.85, 0.3);
+
+const MAX_INPUT_LENGTH: usize = 64;
 
 
 #[derive(Component)]
     enum MenuButtonAction {
         Play,
+        NewWorld,
+        CreateWorld,
+        BackToMainMenu,
         Quit,
     }
 
 
+// Screen of the menu shown while the game is stopped.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
+enum MenuState {
+    Main,
+    CreateWorld,
+    #[default]
+    Disabled,
+}
+
+
+// What is typed and picked on the create world screen, filled in from the command line.
+#[derive(Resource)]
+pub struct CreateWorldInput {
+    pub seed: String,
+    // Name of the preset.
+    pub world_type: String,
+    // Only used by superflat worlds.
+    pub layers: String,
+    // Field the keyboard types into.
+    pub editing: InputField,
+    // Why the world couldn't be created, shown under the world types.
+    pub error: String,
+}
+
+
+impl Default for CreateWorldInput {
+    fn default() -> Self {
+        CreateWorldInput {
+            seed: String::new(),
+            world_type: "default".to_string(),
+            layers: DEFAULT_LAYERS.to_string(),
+            editing: InputField::Seed,
+            error: String::new(),
+        }
+    }
+}
+
+
+impl CreateWorldInput {
+    fn field_mut(&mut self, field: InputField) -> &mut String {
+        match field {
+            InputField::Seed => &mut self.seed,
+            InputField::Layers => &mut self.layers,
+        }
+    }
+}
+
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputField {
+    Seed,
+    Layers,
+}
+
+
+// Text showing what was typed into a field.
+#[derive(Component)]
+struct FieldText(InputField);
+
+
+// Button picking a world type, by its preset name.
+#[derive(Component)]
+struct WorldTypeButton(&'static str);
+
+
+// Tag of the text telling why the world couldn't be created.
+#[derive(Component)]
+struct CreateWorldError;
+
+
 // Tag component used to tag entities added on the main menu screen.
 #[derive(Component)]
 struct OnMainMenuScreen;
 
 
+// Tag component used to tag entities added on the create world screen.
+#[derive(Component)]
+struct OnCreateWorldScreen;
+
+
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app
-            .add_systems(OnEnter(GameState::Stopped), main_menu_setup)
-            .add_systems(OnExit(GameState::Stopped), despawn_screen::<OnMainMenuScreen>)
-            .add_systems(Update, (menu_action, button_system).run_if(in_state(GameState::Stopped)));
+            .init_state::<MenuState>()
+            .init_resource::<CreateWorldInput>()
+            .add_systems(OnEnter(GameState::Stopped), menu_setup)
+            .add_systems(OnEnter(MenuState::Main), main_menu_setup)
+            .add_systems(OnExit(MenuState::Main), despawn_screen::<OnMainMenuScreen>)
+            .add_systems(OnEnter(MenuState::CreateWorld), create_world_setup)
+            .add_systems(OnExit(MenuState::CreateWorld), despawn_screen::<OnCreateWorldScreen>)
+            .add_systems(Update, (menu_action, button_system).run_if(in_state(GameState::Stopped)))
+            .add_systems(Update, (
+                focus_field,
+                world_type_button,
+                type_text,
+                update_create_world_text
+            ).chain().run_if(in_state(MenuState::CreateWorld)));
     }
 }
 
 
-pub fn main_menu_setup(mut commands: Commands) {
+// The first time the menu opens with a seed or world type from the command line, it goes
+// straight to creating that world.
+fn menu_setup(mut menu_state: ResMut<NextState<MenuState>>, input: Res<CreateWorldInput>, mut opened: Local<bool>) {
+    if !*opened && (!input.seed.is_empty() || input.world_type != "default") {
+        menu_state.set(MenuState::CreateWorld);
+    } else {
+        menu_state.set(MenuState::Main);
+    }
+
+    *opened = true;
+}
+
+
+pub fn main_menu_setup(mut commands: Commands, storage: Res<WorldStorage>) {
     // Common style for all buttons on the screen
     let button_style = Style {
         width: Val::Px(250.0),
@@ -91,6 +198,18 @@ pub fn main_menu_setup(mut commands: Commands) {
                         }),
                     );
 
+                    // Display the seed and world type of the saved world, so it can be shared.
+                    if let Some(level) = &storage.level {
+                        parent.spawn(TextBundle::from_section(
+                            format!("Seed: {}, {} world", level.seed, level.preset.name()),
+                            TextStyle {
+                                font_size: 25.0,
+                                color: TEXT_COLOR,
+                                ..default()
+                            },
+                        ));
+                    }
+
                     // Display button for each action available from the main menu.
                     parent
                         .spawn((
@@ -107,6 +226,21 @@ pub fn main_menu_setup(mut commands: Commands) {
                                 button_text_style.clone(),
                             ));
                         });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::NewWorld,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "New World",
+                                button_text_style.clone(),
+                            ));
+                        });
                     parent
                         .spawn((
                             ButtonBundle {
@@ -124,10 +258,288 @@ pub fn main_menu_setup(mut commands: Commands) {
 }
 
 
+pub fn create_world_setup(mut commands: Commands, input: Res<CreateWorldInput>) {
+    let button_style = Style {
+        width: Val::Px(250.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+    let option_style = Style {
+        width: Val::Px(140.0),
+        height: Val::Px(45.0),
+        margin: UiRect::all(Val::Px(5.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let field_style = Style {
+        width: Val::Px(600.0),
+        min_height: Val::Px(45.0),
+        margin: UiRect::horizontal(Val::Px(40.0)),
+        padding: UiRect::horizontal(Val::Px(10.0)),
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let small_text_style = TextStyle {
+        font_size: 25.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+    let hint_text_style = TextStyle {
+        font_size: 20.0,
+        color: HINT_COLOR,
+        ..default()
+    };
+    let hint_style = Style {
+        margin: UiRect::all(Val::Px(10.0)),
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnCreateWorldScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::CRIMSON.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(
+                        TextBundle::from_section(
+                            "Create World",
+                            TextStyle {
+                                font_size: 60.0,
+                                color: TEXT_COLOR,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(30.0)),
+                            ..default()
+                        }),
+                    );
+
+                    // Text fields are buttons, clicking one makes the keyboard type into it.
+                    for (field, hint) in [
+                        (InputField::Seed, "A number, or any text. Leave empty for a random seed."),
+                        (InputField::Layers, "Layers of a superflat world from the bottom up, like 3*dirt, grass."),
+                    ] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: field_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                field,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    TextBundle::from_section(field_text(&input, field), small_text_style.clone()),
+                                    FieldText(field),
+                                ));
+                            });
+                        parent.spawn(TextBundle::from_section(hint, hint_text_style.clone()).with_style(hint_style.clone()));
+                    }
+
+                    // Display a button for each world type, the picked one selected.
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            for world_type in WorldPreset::NAMES {
+                                let mut button = parent.spawn((
+                                    ButtonBundle {
+                                        style: option_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    WorldTypeButton(world_type),
+                                ));
+                                button.with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(world_type_label(world_type), small_text_style.clone()));
+                                });
+                                if input.world_type == world_type {
+                                    button.insert(SelectedOption);
+                                }
+                            }
+                        });
+
+                    // Why the world couldn't be created, if it couldn't.
+                    parent.spawn((
+                        TextBundle::from_section(input.error.clone(), TextStyle {
+                            font_size: 20.0,
+                            color: ERROR_COLOR,
+                            ..default()
+                        })
+                        .with_style(hint_style),
+                        CreateWorldError,
+                    ));
+
+                    parent
+                        .spawn(NodeBundle::default())
+                        .with_children(|parent| {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    MenuButtonAction::CreateWorld,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        "Create",
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style,
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    MenuButtonAction::BackToMainMenu,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section("Back", button_text_style));
+                                });
+                        });
+                });
+        });
+}
+
+
+// Button label of a preset name: "superflat" is shown as "Superflat".
+fn world_type_label(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+
+fn field_text(input: &CreateWorldInput, field: InputField) -> String {
+    let (label, value) = match field {
+        InputField::Seed => ("Seed", &input.seed),
+        InputField::Layers => ("Layers", &input.layers),
+    };
+    let cursor = if input.editing == field { "_" } else { "" };
+
+    format!("{}: {}{}", label, value, cursor)
+}
+
+
+fn type_text(
+    mut characters: EventReader<ReceivedCharacter>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut input: ResMut<CreateWorldInput>,
+) {
+    let editing = input.editing;
+
+    for event in characters.read() {
+        // Backspace and the like arrive as characters too.
+        for character in event.char.chars().filter(|character| !character.is_control()) {
+            let text = input.field_mut(editing);
+            if text.chars().count() < MAX_INPUT_LENGTH {
+                text.push(character);
+            }
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::Backspace) {
+        input.field_mut(editing).pop();
+    }
+}
+
+
+fn focus_field(
+    interaction_query: Query<(&Interaction, &InputField), Changed<Interaction>>,
+    mut input: ResMut<CreateWorldInput>,
+) {
+    for (interaction, field) in &interaction_query {
+        if *interaction == Interaction::Pressed && input.editing != *field {
+            input.editing = *field;
+        }
+    }
+}
+
+
+fn world_type_button(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &WorldTypeButton, Entity), Changed<Interaction>>,
+    mut selected_query: Query<(Entity, &mut BackgroundColor), With<SelectedOption>>,
+    mut input: ResMut<CreateWorldInput>,
+) {
+    for (interaction, world_type, entity) in &interaction_query {
+        if *interaction == Interaction::Pressed && input.world_type != world_type.0 {
+            for (previous_button, mut previous_color) in &mut selected_query {
+                *previous_color = NORMAL_BUTTON.into();
+                commands.entity(previous_button).remove::<SelectedOption>();
+            }
+            commands.entity(entity).insert(SelectedOption);
+            input.world_type = world_type.0.to_string();
+        }
+    }
+}
+
+
+fn update_create_world_text(
+    input: Res<CreateWorldInput>,
+    mut field_query: Query<(&mut Text, &FieldText)>,
+    mut error_query: Query<&mut Text, (With<CreateWorldError>, Without<FieldText>)>,
+) {
+    if !input.is_changed() {
+        return;
+    }
+
+    for (mut text, field) in &mut field_query {
+        text.sections[0].value = field_text(&input, field.0);
+    }
+    for mut text in &mut error_query {
+        text.sections[0].value.clone_from(&input.error);
+    }
+}
+
+
 fn menu_action(
+    mut commands: Commands,
     interaction_query: Query<(&Interaction, &MenuButtonAction), (Changed<Interaction>, With<Button>)>,
+    storage: Res<WorldStorage>,
+    mut input: ResMut<CreateWorldInput>,
     mut app_exit_events: EventWriter<AppExit>,
-    mut game_state: ResMut<NextState<GameState>>) {
+    mut game_state: ResMut<NextState<GameState>>,
+    mut menu_state: ResMut<NextState<MenuState>>) {
 
     for (interaction, menu_button_action) in &interaction_query {
         if *interaction == Interaction::Pressed {
@@ -135,8 +547,29 @@ fn menu_action(
                 MenuButtonAction::Quit => {
                     app_exit_events.send(AppExit);
                 }
+                // Without a saved world to continue, pick a seed for a new one first.
+                MenuButtonAction::Play if storage.level.is_none() => {
+                    menu_state.set(MenuState::CreateWorld);
+                }
                 MenuButtonAction::Play => {
                     game_state.set(GameState::Running);
+                    menu_state.set(MenuState::Disabled);
+                }
+                MenuButtonAction::NewWorld => {
+                    menu_state.set(MenuState::CreateWorld);
+                }
+                MenuButtonAction::CreateWorld => match WorldPreset::parse(&input.world_type, &input.layers) {
+                    Ok(preset) => {
+                        commands.insert_resource(NewWorld { seed: parse_seed(&input.seed), preset });
+                        input.error.clear();
+                        game_state.set(GameState::Running);
+                        menu_state.set(MenuState::Disabled);
+                    }
+                    Err(e) => input.error = format!("Can't create the world: {}.", e),
+                },
+                MenuButtonAction::BackToMainMenu => {
+                    input.error.clear();
+                    menu_state.set(MenuState::Main);
                 }
             }
         }
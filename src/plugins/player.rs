@@ -2,9 +2,10 @@ use bevy::prelude::*;
 use bevy::time::Stopwatch;
 use bevy_rapier3d::prelude::*;
 
-use components::{Player, JumpDuration};
+use components::{Player, PlayerCamera, JumpDuration};
 
 use crate::{GameState, GameGarbage, cleanup};
+use crate::plugins::world::{SeededPerlin, setup_random, storage::WorldStorage, chunk::systems::surface_height};
 
 use self::systems::player_movement::{movement_system, jump_system, camera_rotation_system};
 use self::systems::block_manipulation::{block_breaking_system, block_placing_system};
@@ -19,7 +20,7 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app
-            .add_systems(OnEnter(GameState::Running), player_setup)
+            .add_systems(OnEnter(GameState::Running), player_setup.after(setup_random))
             .add_systems(OnExit(GameState::Running), (cleanup::<GameGarbage>, unlock_cursor))
             .add_systems(Update, (
                 lock_cursor,
@@ -33,11 +34,27 @@ impl Plugin for PlayerPlugin {
 }
 
 
-pub fn player_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn player_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    storage: Res<WorldStorage>,
+    perlin: Res<SeededPerlin>,
+    mut camera_query: Query<&mut Transform, With<PlayerCamera>>,
+) {
+    // New worlds start above the ground in the middle, clear of any tree there.
+    let mut translation = Vec3::new(0.0, surface_height(&perlin, 0, 0) as f32 + 10.0, 0.0);
+
+    if let Some(level) = &storage.level {
+        translation = level.player_position;
+
+        if let Ok(mut camera_transform) = camera_query.get_single_mut() {
+            camera_transform.rotation = level.camera_rotation;
+        }
+    }
 
     let player = commands.spawn((Name::new("Player"), PbrBundle {
         transform: Transform {
-            translation: Vec3::new(0.0, 96.0, 0.0),
+            translation,
             ..Default::default()
         },
         ..default()
@@ -1,10 +1,8 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
-use crate::CHUNK_HEIGHT;
 use crate::plugins::player::components::{Player, PlayerCamera};
-use crate::plugins::world::{ChunkQueue, chunk::components::BlockType};
-use crate::plugins::world::systems::enque_chunk;
+use crate::plugins::world::{ChunkQueue, FluidQueue, gravity::GravityQueue, chunk::{components::BlockType, light::world_position}};
 use crate::{CHUNK_WIDTH, plugins::world::WorldMap};
 
 
@@ -14,6 +12,8 @@ pub fn block_breaking_system(
     mut world_map: ResMut<WorldMap>,
     buttons: Res<ButtonInput<MouseButton>>,
     mut chunk_queue: ResMut<ChunkQueue>,
+    mut fluid_queue: ResMut<FluidQueue>,
+    mut gravity_queue: ResMut<GravityQueue>,
 ) {
     let camera_transform = camera_query.single();
 
@@ -28,20 +28,15 @@ pub fn block_breaking_system(
                                                 (hit.y) as usize,
                                                 (hit.z - (chunk_pos.1 as f32 *  CHUNK_WIDTH as f32)) as usize);
 
-            let index = x + y * CHUNK_WIDTH + z * CHUNK_WIDTH*CHUNK_HEIGHT;
-            let hitblock = world_map.chunks[&chunk_pos][index];
+            let hitblock = world_map.chunks[&chunk_pos].get(x, y, z);
 
-            if hitblock != BlockType::Air &&
-               hitblock != BlockType::BedRock {
-                world_map.chunks.get_mut(&chunk_pos).unwrap()[index] = BlockType::Air;
+            if hitblock.is_breakable() {
+                let lit = world_map.set_block(chunk_pos, x, y, z, BlockType::AIR);
+                chunk_queue.mark_edited(chunk_pos, x, y, z);
+                chunk_queue.mark_lit(lit);
+                fluid_queue.schedule_around(world_position(chunk_pos, x, y, z));
+                gravity_queue.schedule(world_position(chunk_pos, x, y, z));
             }
-
-            enque_chunk(&mut chunk_queue, chunk_pos);
-            // this is cringe, TODO: rework
-            enque_chunk(&mut chunk_queue, (chunk_pos.0-1, chunk_pos.1));
-            enque_chunk(&mut chunk_queue, (chunk_pos.0+1, chunk_pos.1));
-            enque_chunk(&mut chunk_queue, (chunk_pos.0, chunk_pos.1-1));
-            enque_chunk(&mut chunk_queue, (chunk_pos.0, chunk_pos.1+1));
         }
     }
 }
@@ -52,6 +47,8 @@ pub fn block_placing_system(
     mut world_map: ResMut<WorldMap>,
     buttons: Res<ButtonInput<MouseButton>>,
     mut chunk_queue: ResMut<ChunkQueue>,
+    mut fluid_queue: ResMut<FluidQueue>,
+    mut gravity_queue: ResMut<GravityQueue>,
 ) {
     let camera_transform = camera_query.single();
 
@@ -78,19 +75,14 @@ pub fn block_placing_system(
                                                   (hit.y) as usize,
                                                   (hit.z - (chunk_pos.1 as f32 *  CHUNK_WIDTH as f32)) as usize);
 
-            let index = x + y * CHUNK_WIDTH + z * CHUNK_WIDTH * CHUNK_HEIGHT;
-            if world_map.chunks[&chunk_pos][index] == BlockType::Air
-            || world_map.chunks[&chunk_pos][index] == BlockType::Water {
-                world_map.chunks.get_mut(&chunk_pos).unwrap()[index] = BlockType::Stone;
+            let block = world_map.chunks[&chunk_pos].get(x, y, z);
+            if !block.is_solid() {
+                let lit = world_map.set_block(chunk_pos, x, y, z, BlockType::STONE);
+                chunk_queue.mark_edited(chunk_pos, x, y, z);
+                chunk_queue.mark_lit(lit);
+                fluid_queue.schedule_around(world_position(chunk_pos, x, y, z));
+                gravity_queue.schedule(world_position(chunk_pos, x, y, z));
             }
-
-            enque_chunk(&mut chunk_queue, chunk_pos);
-            // this is cringe, TODO: rework
-            enque_chunk(&mut chunk_queue, (chunk_pos.0-1, chunk_pos.1));
-            enque_chunk(&mut chunk_queue, (chunk_pos.0+1, chunk_pos.1));
-            enque_chunk(&mut chunk_queue, (chunk_pos.0, chunk_pos.1-1));
-            enque_chunk(&mut chunk_queue, (chunk_pos.0, chunk_pos.1+1));
         }
     }
 }
-
@@ -1,80 +1,538 @@
-use std::collections::HashMap;
-use std::time::SystemTime;
-use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use bevy::{prelude::*, app::AppExit, asset::io::file::FileAssetReader, tasks::Task};
 use noise::Perlin;
+use rand::{rngs::StdRng, SeedableRng};
 
-use crate::{GameState, CHUNK_WIDTH, CHUNK_HEIGHT};
+use crate::{GameState, GameGarbage, CHUNK_WIDTH, CHUNK_HEIGHT, SECTION_HEIGHT, SECTION_COUNT};
+use crate::plugins::player::systems::block_manipulation::{block_breaking_system, block_placing_system};
 
-use self::{systems::{generate_chunks_from_player_movement, deque_chunks, unload_far_chunks}, chunk::components::BlockType};
+use self::{systems::{generate_chunks_from_player_movement, receive_generated_chunks, deque_chunks, receive_chunk_meshes, unload_far_chunks, save_world, toggle_greedy_meshing, log_player_biome, rebuild_edited_chunks, evict_chunk_data, flow_water, FLUID_TICK, random_tick_sections, RANDOM_TICK}, chunk::{components::BlockType, data::ChunkData, material::ChunkMaterial, pending::{PendingBlocks, apply_pending_blocks, merge_pending}, seeding::{noise_seed, random_seed, Stream}, preset::WorldPreset, light::{update_light, stitch_chunk_light, lit_sections, world_position, Position}, registry::{load_block_registry, BLOCKS_PATH}, biome::{load_biome_registry, BIOMES_PATH}, ore::{load_ore_registry, ORES_PATH}, atlas::{build_block_atlas, TEXTURES_DIR}, systems::{GeneratedChunk, ChunkMeshes}}};
+use self::storage::{WorldStorage, SAVE_DIR};
+use self::time::{setup_world_time, advance_world_time, update_sky, change_day_length};
+use self::gravity::{GravityQueue, drop_unsupported_blocks, land_falling_blocks};
 
 pub(crate) mod chunk;
 pub(crate) mod systems;
+pub(crate) mod storage;
+pub(crate) mod time;
+pub(crate) mod gravity;
+#[cfg(test)]
+pub(crate) mod testing;
 
 
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
+        // The registries are set once here, before anything runs. Biomes and ores name the
+        // blocks they use, so they are loaded after the block registry.
+        let atlas = build_block_atlas(asset_path(TEXTURES_DIR)).expect("[E] Could not build the block texture atlas!");
+        load_block_registry(asset_path(BLOCKS_PATH), &atlas.tiles).expect("[E] Could not load the block registry!");
+        load_biome_registry(asset_path(BIOMES_PATH)).expect("[E] Could not load the biome registry!");
+        load_ore_registry(asset_path(ORES_PATH)).expect("[E] Could not load the ore registry!");
+        let atlas_image = app.world.resource_mut::<Assets<Image>>().add(atlas.image);
+
         app
-            .insert_resource(WorldMap {
-                chunks: HashMap::new(),
-                chunk_entities: HashMap::new(),
-                water_chunk_entities: HashMap::new(),
-                reserved_chunk_data: HashMap::new()
+            .insert_resource(BlockAtlasImage(atlas_image))
+            .init_resource::<WorldMap>()
+            .init_resource::<ChunkQueue>()
+            .init_resource::<ChunkTasks>()
+            .init_resource::<FluidQueue>()
+            .init_resource::<GravityQueue>()
+            .init_resource::<RandomTicks>()
+            .insert_resource(MeshingSettings { greedy: true })
+            .add_plugins(MaterialPlugin::<ChunkMaterial> {
+                prepass_enabled: false,
+                ..default()
             })
-            .insert_resource(ChunkQueue { queue: vec![], is_next_ready: true })
-            .add_systems(OnEnter(GameState::Running), setup_random)
+            .insert_resource(WorldStorage::open(SAVE_DIR))
+            .add_systems(Startup, setup_chunk_materials)
+            .add_systems(OnEnter(GameState::Running), (start_new_world, setup_random, setup_world_time, show_seed).chain())
+            .add_systems(OnExit(GameState::Running), save_world)
             .add_systems(Update, (
                 generate_chunks_from_player_movement,
+                receive_generated_chunks,
                 deque_chunks,
-                unload_far_chunks
-            ).run_if(in_state(GameState::Running)));
+                receive_chunk_meshes,
+                unload_far_chunks,
+                evict_chunk_data,
+                toggle_greedy_meshing,
+                log_player_biome
+            ).run_if(in_state(GameState::Running)))
+            .add_systems(Update, (
+                advance_world_time,
+                update_sky,
+                change_day_length
+            ).chain().run_if(in_state(GameState::Running)))
+            .add_systems(Update, flow_water
+                .after(block_breaking_system)
+                .after(block_placing_system)
+                .run_if(in_state(GameState::Running)))
+            .add_systems(Update, (land_falling_blocks, drop_unsupported_blocks)
+                .chain()
+                .after(block_breaking_system)
+                .after(block_placing_system)
+                .run_if(in_state(GameState::Running)))
+            .add_systems(Update, random_tick_sections.run_if(in_state(GameState::Running)))
+            .add_systems(Update, rebuild_edited_chunks
+                .after(block_breaking_system)
+                .after(block_placing_system)
+                .after(flow_water)
+                .after(random_tick_sections)
+                .after(drop_unsupported_blocks)
+                .run_if(in_state(GameState::Running)))
+            .add_systems(Last, save_world.run_if(in_state(GameState::Running).and_then(on_event::<AppExit>())));
     }
 }
 
 
-#[derive(Resource)]
+// Files the world reads itself rather than through the asset server are looked up where the
+// asset server looks: next to the executable, or in the crate when run through cargo.
+pub fn asset_path(path: &str) -> PathBuf {
+    FileAssetReader::get_base_path().join(path)
+}
+
+
+#[derive(Resource, Default)]
 pub struct WorldMap {
-    pub chunks: HashMap<(i32, i32), [BlockType; CHUNK_WIDTH*CHUNK_HEIGHT*CHUNK_WIDTH]>,
-    pub chunk_entities: HashMap<(i32,i32), Entity>,
-    pub water_chunk_entities: HashMap<(i32, i32), Entity>,
-    pub reserved_chunk_data: HashMap<(i32, i32), [BlockType; CHUNK_WIDTH*CHUNK_HEIGHT*CHUNK_WIDTH]>,
+    pub chunks: HashMap<(i32, i32), ChunkData>,
+    // One mesh entity per non-empty chunk section.
+    pub chunk_entities: HashMap<(i32,i32), [Option<Entity>; SECTION_COUNT]>,
+    pub water_chunk_entities: HashMap<(i32, i32), [Option<Entity>; SECTION_COUNT]>,
+    // Blocks features of generated chunks placed in chunks that aren't loaded yet.
+    pub pending_blocks: HashMap<(i32, i32), PendingBlocks>,
+    // Pending blocks placed in each loaded chunk, which later writes to the same cell are weighed against.
+    pub applied_pending: HashMap<(i32, i32), PendingBlocks>,
+    // Chunks read from disk. They were saved with their neighbours around, so they
+    // already hold every block the neighbours' features placed in them.
+    pub stored_chunks: HashSet<(i32, i32)>,
+    // Chunks edited since they were last written to disk.
+    pub modified_chunks: HashSet<(i32, i32)>,
+    // Bumped on every edit, so meshes built from older chunk data can be told apart.
+    pub chunk_revisions: HashMap<(i32, i32), u32>,
+    // Seconds since startup when a chunk was last around the player, used to pick chunks to evict.
+    pub last_access: HashMap<(i32, i32), f64>,
 }
 
 
-#[derive(Resource)]
+impl WorldMap {
+    // Returns the sections whose light changed along with the block.
+    pub fn set_block(&mut self, chunk_pos: (i32, i32), x: usize, y: usize, z: usize, block: BlockType) -> HashSet<((i32, i32), usize)> {
+        let Some(blocks) = self.chunks.get_mut(&chunk_pos) else {
+            return HashSet::new();
+        };
+
+        blocks.set(x, y, z, block);
+        self.modified_chunks.insert(chunk_pos);
+        self.bump_revisions(chunk_pos, x, y, z);
+
+        let lit = update_light(&mut self.chunks, world_position(chunk_pos, x, y, z));
+        self.relight(&lit)
+    }
+
+
+    // Meshes are built from their chunk and the chunks around it, so a block on a border
+    // outdates the meshes on both sides of it.
+    fn bump_revisions(&mut self, chunk_pos: (i32, i32), x: usize, y: usize, z: usize) {
+        let chunks: HashSet<(i32, i32)> = affected_sections(chunk_pos, x, y, z).into_iter()
+            .map(|(chunk, _)| chunk)
+            .filter(|chunk| self.chunks.contains_key(chunk))
+            .collect();
+
+        for chunk in chunks {
+            *self.chunk_revisions.entry(chunk).or_default() += 1;
+        }
+    }
+
+
+    // Bump the revision of every chunk whose light changed, so meshes built from the old
+    // light are thrown away. Returns the sections to remesh.
+    pub fn relight(&mut self, changed: &[Position]) -> HashSet<((i32, i32), usize)> {
+        let sections = lit_sections(changed);
+        let chunks: HashSet<(i32, i32)> = sections.iter().map(|(chunk, _)| *chunk).collect();
+        for chunk in chunks {
+            *self.chunk_revisions.entry(chunk).or_default() += 1;
+        }
+
+        sections
+    }
+
+
+    // Fill in blocks a neighbour's features placed in a loaded chunk. The neighbour only
+    // hands them over once, so the chunk is marked modified and saved before it is evicted,
+    // or they would be missing when it is generated again. Returns the positions that changed.
+    pub fn apply_pending(&mut self, chunk_pos: (i32, i32), pending: &PendingBlocks) -> Vec<(usize, usize, usize)> {
+        let Some(blocks) = self.chunks.get_mut(&chunk_pos) else {
+            return vec![];
+        };
+
+        let changed = apply_pending_blocks(blocks, pending, self.applied_pending.entry(chunk_pos).or_default());
+        if !changed.is_empty() {
+            self.modified_chunks.insert(chunk_pos);
+        }
+        for &(x, y, z) in &changed {
+            self.bump_revisions(chunk_pos, x, y, z);
+        }
+
+        changed
+    }
+
+
+    // Put a chunk that finished loading or generating in the world, along with the blocks
+    // its features placed in its neighbours. Returns the sections whose blocks or light changed.
+    pub fn insert_generated(&mut self, generated: GeneratedChunk) -> HashSet<((i32, i32), usize)> {
+        let GeneratedChunk { position, mut blocks, pending, loaded, .. } = generated;
+
+        let waiting = self.pending_blocks.remove(&position);
+        let mut placed = vec![];
+        if loaded {
+            self.stored_chunks.insert(position);
+        }
+        else if let Some(waiting) = waiting {
+            placed = apply_pending_blocks(&mut blocks, &waiting, self.applied_pending.entry(position).or_default());
+        }
+        if !placed.is_empty() {
+            self.modified_chunks.insert(position);
+        }
+        self.chunks.insert(position, blocks);
+
+        // The chunk was lit on its own, before the waiting blocks went in.
+        let mut lit = stitch_chunk_light(&mut self.chunks, position);
+        for (x, y, z) in placed {
+            lit.extend(update_light(&mut self.chunks, world_position(position, x, y, z)));
+        }
+
+        let mut changed = HashSet::new();
+
+        for (target, blocks) in pending {
+            if self.stored_chunks.contains(&target) {
+                continue;
+            }
+
+            if !self.chunks.contains_key(&target) {
+                merge_pending(self.pending_blocks.entry(target).or_default(), blocks);
+                continue;
+            }
+
+            for (x, y, z) in self.apply_pending(target, &blocks) {
+                lit.extend(update_light(&mut self.chunks, world_position(target, x, y, z)));
+                changed.extend(affected_sections(target, x, y, z));
+            }
+        }
+
+        changed.extend(self.relight(&lit));
+        changed
+    }
+
+
+    // Drop the data of chunks, writing the modified ones to disk first, and the blocks
+    // waiting for chunks nothing around is left to place. Nothing is dropped if they can't be written.
+    pub fn evict(&mut self, chunks: &[(i32, i32)], storage: &WorldStorage) -> io::Result<()> {
+        let modified = chunks.iter()
+            .filter(|chunk| self.modified_chunks.contains(chunk))
+            .filter_map(|chunk| self.chunks.get(chunk).map(|blocks| (*chunk, blocks)));
+        storage.save_chunks(modified)?;
+
+        for chunk in chunks {
+            self.chunks.remove(chunk);
+            self.modified_chunks.remove(chunk);
+            self.chunk_revisions.remove(chunk);
+            self.last_access.remove(chunk);
+            self.stored_chunks.remove(chunk);
+            self.applied_pending.remove(chunk);
+        }
+
+        // Features only reach into the chunks next to theirs, and hand their blocks over
+        // again when their chunk comes back, so blocks waiting for a chunk are only kept
+        // while one of the chunks around it is in memory.
+        let chunks = &self.chunks;
+        self.pending_blocks.retain(|target, _| (-1..=1).any(|x| (-1..=1).any(|z| chunks.contains_key(&(target.0 + x, target.1 + z)))));
+
+        Ok(())
+    }
+
+
+    // Block at a position in the world, if its chunk is loaded.
+    pub fn block_at(&self, position: Vec3) -> Option<BlockType> {
+        let position = position.floor();
+        if position.y < 0.0 || position.y >= CHUNK_HEIGHT as f32 {
+            return None;
+        }
+
+        let width = CHUNK_WIDTH as i32;
+        let (x, z) = (position.x as i32, position.z as i32);
+        let blocks = self.chunks.get(&(x.div_euclid(width), z.div_euclid(width)))?;
+
+        Some(blocks.get(x.rem_euclid(width) as usize, position.y as usize, z.rem_euclid(width) as usize))
+    }
+
+
+    pub fn revision(&self, chunk_pos: (i32, i32)) -> u32 {
+        self.chunk_revisions.get(&chunk_pos).copied().unwrap_or_default()
+    }
+}
+
+
+#[derive(Resource, Clone)]
 pub struct SeededPerlin {
-    pub seed: u32,
+    pub seed: u64,
+    pub preset: WorldPreset,
     pub terrain_noise: Perlin,
     pub tree_noise: Perlin,
     pub temperature_noise: Perlin,
     pub moisture_noise: Perlin,
+    pub cave_noise: Perlin,
+    pub tunnel_noise: Perlin,
+}
+
+
+impl SeededPerlin {
+    pub fn new(seed: u64, preset: WorldPreset) -> Self {
+        SeededPerlin {
+            seed,
+            preset,
+            terrain_noise: Perlin::new(noise_seed(seed, Stream::TerrainNoise)),
+            tree_noise: Perlin::new(noise_seed(seed, Stream::TreeNoise)),
+            temperature_noise: Perlin::new(noise_seed(seed, Stream::TemperatureNoise)),
+            moisture_noise: Perlin::new(noise_seed(seed, Stream::MoistureNoise)),
+            cave_noise: Perlin::new(noise_seed(seed, Stream::CaveNoise)),
+            tunnel_noise: Perlin::new(noise_seed(seed, Stream::TunnelNoise)),
+        }
+    }
 }
 
 
+// Set by the create world screen: the next game starts this world in place of the saved one.
 #[derive(Resource)]
+pub struct NewWorld {
+    pub seed: u64,
+    pub preset: WorldPreset,
+}
+
+
+#[derive(Resource, Default)]
 pub struct ChunkQueue {
-    pub queue: Vec<(i32, i32)>,
-    pub is_next_ready: bool,
+    // Chunk sections waiting to be (re)meshed on the task pool, by chunk.
+    pub dirty: HashMap<(i32, i32), HashSet<usize>>,
+    // Sections changed by the player, rebuilt on the main thread in the frame of the edit.
+    pub edited: HashMap<(i32, i32), HashSet<usize>>,
 }
 
 
-fn setup_random(mut commands: Commands) {
+impl ChunkQueue {
+    pub fn mark_dirty(&mut self, chunk_pos: (i32, i32), section: usize) {
+        self.dirty.entry(chunk_pos).or_default().insert(section);
+    }
+
 
-    let seed = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
-        .expect("[E] SystemTime before UNIX EPOCH!").as_secs() as u32;
+    pub fn mark_edited(&mut self, chunk_pos: (i32, i32), x: usize, y: usize, z: usize) {
+        for (chunk, section) in affected_sections(chunk_pos, x, y, z) {
+            self.edited.entry(chunk).or_default().insert(section);
+        }
+    }
 
-    let terrain_perlin = Perlin::new(seed);
-    let tree_perlin = Perlin::new(seed*2);
-    let temperature_perlin = Perlin::new(seed+20);
-    let moisture_perlin = Perlin::new(seed+30);
 
-    commands.insert_resource(
-        SeededPerlin {
-            seed,
-            terrain_noise: terrain_perlin,
-            tree_noise: tree_perlin,
-            temperature_noise: temperature_perlin,
-            moisture_noise: moisture_perlin
-        });
+    // Sections lit differently after an edit are rebuilt along with it.
+    pub fn mark_lit(&mut self, sections: HashSet<((i32, i32), usize)>) {
+        for (chunk, section) in sections {
+            self.edited.entry(chunk).or_default().insert(section);
+        }
+    }
+
+
+    // Blocks changed by the world itself rather than the player are remeshed on the task pool,
+    // along with the sections lit differently. Chunks that aren't built yet mesh everything when they are.
+    pub fn mark_changed(&mut self, world_map: &WorldMap, chunk_pos: (i32, i32), x: usize, y: usize, z: usize, lit: HashSet<((i32, i32), usize)>) {
+        for (chunk, section) in affected_sections(chunk_pos, x, y, z).into_iter().chain(lit) {
+            if world_map.chunk_entities.contains_key(&chunk) {
+                self.mark_dirty(chunk, section);
+            }
+        }
+    }
+}
+
+
+// Every section whose mesh can see the block at (x, y, z). Faces and AO of a block
+// reach one block out, diagonals included, so blocks on a chunk or section border
+// also affect the neighbours across it.
+pub fn affected_sections(chunk_pos: (i32, i32), x: usize, y: usize, z: usize) -> HashSet<((i32, i32), usize)> {
+    let width = CHUNK_WIDTH as i32;
+    let mut sections = HashSet::new();
+
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let (nx, ny, nz) = (x as i32 + dx, y as i32 + dy, z as i32 + dz);
+                if ny < 0 || ny >= CHUNK_HEIGHT as i32 {
+                    continue;
+                }
+
+                let chunk = (chunk_pos.0 + nx.div_euclid(width), chunk_pos.1 + nz.div_euclid(width));
+                sections.insert((chunk, ny as usize / SECTION_HEIGHT));
+            }
+        }
+    }
+
+    sections
+}
+
+
+// Chunks being loaded, generated or meshed on the async compute pool.
+#[derive(Resource, Default)]
+pub struct ChunkTasks {
+    pub generating: HashMap<(i32, i32), Task<GeneratedChunk>>,
+    pub meshing: HashMap<(i32, i32), Task<ChunkMeshes>>,
+}
+
+
+// Blocks whose water may flow on the next fluid tick. Water only moves every FLUID_TICK
+// seconds, and at most MAX_FLUID_UPDATES blocks are looked at per tick.
+#[derive(Resource)]
+pub struct FluidQueue {
+    pub scheduled: HashSet<Position>,
+    pub timer: Timer,
+}
+
+
+impl Default for FluidQueue {
+    fn default() -> Self {
+        FluidQueue { scheduled: HashSet::new(), timer: Timer::from_seconds(FLUID_TICK, TimerMode::Repeating) }
+    }
+}
+
+
+impl FluidQueue {
+    // Schedule a block that changed, and everything that could flow into or out of it.
+    pub fn schedule_around(&mut self, position: Position) {
+        self.scheduled.insert(position);
+        for (dx, dy, dz) in [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)] {
+            self.scheduled.insert((position.0 + dx, position.1 + dy, position.2 + dz));
+        }
+    }
+}
+
+
+// Every RANDOM_TICK seconds a few random blocks of each section near the player get to
+// change on their own, like grass spreading or leaves decaying.
+#[derive(Resource)]
+pub struct RandomTicks {
+    pub timer: Timer,
+    pub random: StdRng,
+}
+
+
+impl Default for RandomTicks {
+    fn default() -> Self {
+        RandomTicks { timer: Timer::from_seconds(RANDOM_TICK, TimerMode::Repeating), random: StdRng::from_entropy() }
+    }
+}
+
+
+// Greedy meshing merges faces into larger quads, the naive mesher emits one quad per block face.
+#[derive(Resource)]
+pub struct MeshingSettings {
+    pub greedy: bool,
+}
+
+
+// Block textures stitched together at startup.
+#[derive(Resource)]
+pub struct BlockAtlasImage(pub Handle<Image>);
+
+
+pub const WATER_COLOR: [f32; 4] = [0.25, 0.5, 1.0, 0.75];
+
+
+#[derive(Resource)]
+pub struct ChunkMaterials {
+    pub chunk: Handle<ChunkMaterial>,
+    pub water: Handle<StandardMaterial>,
+}
+
+
+fn setup_chunk_materials(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut chunk_materials: ResMut<Assets<ChunkMaterial>>,
+    atlas_image: Res<BlockAtlasImage>,
+) {
+    commands.insert_resource(ChunkMaterials {
+        chunk: chunk_materials.add(ChunkMaterial {
+            texture: atlas_image.0.clone(),
+            daylight: 1.0,
+        }),
+        water: materials.add(StandardMaterial {
+            base_color: Color::rgba(WATER_COLOR[0], WATER_COLOR[1], WATER_COLOR[2], WATER_COLOR[3]),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            // Seen from underwater, the surface is looked at from behind.
+            cull_mode: None,
+            ..default()
+        }),
+    });
+}
+
+
+// The saved world is moved aside rather than overwritten, and everything loaded from it is
+// dropped, chunks still being generated or meshed included.
+pub fn start_new_world(
+    mut commands: Commands,
+    new_world: Option<Res<NewWorld>>,
+    mut storage: ResMut<WorldStorage>,
+    mut world_map: ResMut<WorldMap>,
+) {
+    if new_world.is_none() {
+        return;
+    }
+
+    match storage.set_aside() {
+        Ok(Some(dir)) => info!("[I] Moved the old world to {}", dir.display()),
+        Ok(None) => {}
+        Err(e) => {
+            error!("[E] Could not move the old world aside, continuing it: {}", e);
+            return;
+        }
+    }
+
+    for entity in world_map.chunk_entities.values().chain(world_map.water_chunk_entities.values()).flatten().flatten() {
+        commands.entity(*entity).despawn_recursive();
+    }
+
+    *world_map = WorldMap::default();
+    commands.insert_resource(ChunkQueue::default());
+    commands.insert_resource(ChunkTasks::default());
+    commands.insert_resource(FluidQueue::default());
+    commands.insert_resource(GravityQueue::default());
+}
+
+
+pub fn setup_random(mut commands: Commands, storage: Res<WorldStorage>, new_world: Option<Res<NewWorld>>) {
+    let perlin = match (&storage.level, new_world) {
+        (Some(level), _) => SeededPerlin::new(level.seed, level.preset.clone()),
+        (None, Some(new_world)) => SeededPerlin::new(new_world.seed, new_world.preset.clone()),
+        (None, None) => SeededPerlin::new(random_seed(), WorldPreset::Default),
+    };
+
+    commands.remove_resource::<NewWorld>();
+    commands.insert_resource(perlin);
+}
+
+
+fn show_seed(mut commands: Commands, perlin: Res<SeededPerlin>) {
+    commands.spawn((
+        TextBundle::from_section(format!("Seed: {}", perlin.seed), TextStyle {
+            font_size: 20.0,
+            color: Color::WHITE,
+            ..default()
+        })
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        GameGarbage,
+    ));
 }
@@ -1,6 +1,18 @@
 use bevy::prelude::*;
 
+pub mod atlas;
+pub mod biome;
 pub mod components;
+pub mod data;
+pub mod fluid;
+pub mod light;
+pub mod material;
+pub mod ore;
+pub mod pending;
+pub mod preset;
+pub mod random_ticks;
+pub mod registry;
+pub mod seeding;
 pub mod systems;
 
 pub struct ChunkPlugin;
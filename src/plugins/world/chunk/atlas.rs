@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use bevy::{prelude::*, render::{render_asset::RenderAssetUsages, render_resource::{Extent3d, TextureDimension, TextureFormat}, texture::{ImageSampler, ImageSamplerDescriptor, ImageFilterMode}}};
+
+
+pub const TEXTURES_DIR: &str = "assets/textures/blocks";
+
+// Every tile is surrounded by PADDING pixels repeating its edge, so neither filtering nor
+// the first MIP_LEVELS mip levels pick up colour from the neighbouring tiles.
+const PADDING: u32 = 4;
+const MIP_LEVELS: u32 = 3;
+
+
+pub struct BlockAtlas {
+    pub image: Image,
+    // Atlas rectangle of every texture as (min u, min v, width, height), by file name without extension.
+    pub tiles: HashMap<String, [f32; 4]>,
+}
+
+
+// Stitch every png in the directory into one atlas. All textures must be the same size.
+pub fn build_block_atlas(dir: impl AsRef<Path>) -> io::Result<BlockAtlas> {
+    let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "png"));
+    paths.sort();
+
+    let mut textures = vec![];
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let texture = image::open(&path).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?.into_rgba8();
+        textures.push((name, texture));
+    }
+
+    let Some((_, first)) = textures.first() else {
+        return Err(invalid("no block textures found".to_string()));
+    };
+    let tile_size = first.width();
+
+    if let Some((name, _)) = textures.iter().find(|(_, texture)| texture.width() != tile_size || texture.height() != tile_size) {
+        return Err(invalid(format!("texture {} is not {}x{} like the others", name, tile_size, tile_size)));
+    }
+
+    // Tiles that don't halve evenly down to the last mip level would blend into their padding.
+    if tile_size % (1 << MIP_LEVELS) != 0 {
+        return Err(invalid(format!("textures are {} pixels wide, which is not a multiple of {}", tile_size, 1 << MIP_LEVELS)));
+    }
+
+    let columns = (textures.len() as f32).sqrt().ceil() as u32;
+    let rows = (textures.len() as u32).div_ceil(columns);
+    let cell = tile_size + PADDING * 2;
+    let (width, height) = (columns * cell, rows * cell);
+
+    let mut data = vec![0; (width * height * 4) as usize];
+    let mut tiles = HashMap::new();
+
+    for (i, (name, texture)) in textures.iter().enumerate() {
+        let (left, top) = ((i as u32 % columns) * cell, (i as u32 / columns) * cell);
+
+        for y in 0 .. cell {
+            for x in 0 .. cell {
+                // Pixels in the padding copy the closest edge pixel.
+                let source_x = x.saturating_sub(PADDING).min(tile_size - 1);
+                let source_y = y.saturating_sub(PADDING).min(tile_size - 1);
+                let pixel = texture.get_pixel(source_x, source_y).0;
+
+                let index = (((top + y) * width + left + x) * 4) as usize;
+                data[index .. index + 4].copy_from_slice(&pixel);
+            }
+        }
+
+        tiles.insert(name.clone(), [
+            (left + PADDING) as f32 / width as f32,
+            (top + PADDING) as f32 / height as f32,
+            tile_size as f32 / width as f32,
+            tile_size as f32 / height as f32,
+        ]);
+    }
+
+    let mut image = Image::new(
+        Extent3d { width, height, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+
+    // Cells are a multiple of 2^(MIP_LEVELS - 1) pixels wide, so every level halves evenly.
+    let mut level = image.data.clone();
+    let (mut level_width, mut level_height) = (width, height);
+    for _ in 1 .. MIP_LEVELS {
+        level = downsample(&level, level_width, level_height);
+        level_width /= 2;
+        level_height /= 2;
+        image.data.extend(&level);
+    }
+
+    image.texture_descriptor.mip_level_count = MIP_LEVELS;
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        mipmap_filter: ImageFilterMode::Linear,
+        ..ImageSamplerDescriptor::nearest()
+    });
+
+    Ok(BlockAtlas { image, tiles })
+}
+
+
+// Average every 2x2 block of pixels.
+fn downsample(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (half_width, half_height) = (width / 2, height / 2);
+    let mut half = vec![0; (half_width * half_height * 4) as usize];
+
+    for y in 0 .. half_height {
+        for x in 0 .. half_width {
+            for channel in 0 .. 4 {
+                let sum: u32 = [(0, 0), (1, 0), (0, 1), (1, 1)].iter()
+                    .map(|(dx, dy)| data[(((y * 2 + dy) * width + x * 2 + dx) * 4 + channel) as usize] as u32)
+                    .sum();
+                half[((y * half_width + x) * 4 + channel) as usize] = (sum / 4) as u8;
+            }
+        }
+    }
+
+    half
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::world::testing::TestDir;
+
+
+    fn write_textures(dir: &Path, size: u32, names: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        for name in names {
+            image::RgbaImage::from_pixel(size, size, image::Rgba([200, 100, 50, 255])).save(dir.join(format!("{}.png", name))).unwrap();
+        }
+    }
+
+
+    #[test]
+    fn textures_are_stitched_into_padded_tiles() {
+        let dir = TestDir::new("atlas");
+        write_textures(dir.path(), 16, &["dirt", "stone", "sand"]);
+
+        let atlas = build_block_atlas(dir.path()).unwrap();
+        let cell = 16 + PADDING * 2;
+
+        // Three tiles are laid out in two columns and two rows, sorted by name.
+        assert_eq!((atlas.image.width(), atlas.image.height()), (cell * 2, cell * 2));
+        assert_eq!(atlas.tiles["stone"], [
+            PADDING as f32 / (cell * 2) as f32,
+            (cell + PADDING) as f32 / (cell * 2) as f32,
+            0.5 * 16.0 / cell as f32,
+            0.5 * 16.0 / cell as f32,
+        ]);
+    }
+
+
+    #[test]
+    fn tiles_that_do_not_halve_down_to_the_last_mip_level_are_refused() {
+        let dir = TestDir::new("atlas-size");
+        write_textures(dir.path(), 12, &["dirt"]);
+
+        let error = build_block_atlas(dir.path()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("not a multiple of 8"));
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::OnceLock;
+use noise::NoiseFn;
+use serde::Deserialize;
+
+use crate::CHUNK_WIDTH;
+use crate::plugins::world::SeededPerlin;
+
+use super::components::{BlockType, Face};
+
+
+pub const BIOMES_PATH: &str = "assets/biomes.ron";
+
+static REGISTRY: OnceLock<BiomeRegistry> = OnceLock::new();
+
+
+// Index of a biome in the biome registry, see assets/biomes.ron. Biomes aren't saved
+// with the world, only the blocks they generated, so their order may change.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct Biome(u8);
+
+
+impl Biome {
+    pub fn properties(&self) -> &'static BiomeProperties {
+        &registry().biomes[self.0 as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        &self.properties().name
+    }
+}
+
+
+pub struct BiomeProperties {
+    pub name: String,
+    // Climate the biome is found in, as (min, max).
+    pub temperature: (f32, f32),
+    pub humidity: (f32, f32),
+    // Top block of the ground above the sea, the blocks under it, and the top of the sea floor.
+    pub surface: BlockType,
+    pub subsurface: BlockType,
+    pub underwater: BlockType,
+    // Tried in order on every surface block, the first one to grow there wins.
+    pub vegetation: Vec<Vegetation>,
+    // Colours grass and leaves are multiplied by.
+    pub grass_tint: [f32; 3],
+    pub foliage_tint: [f32; 3],
+    // Blocks the terrain is raised by, and how much its hills are stretched.
+    pub elevation: f32,
+    pub relief: f32,
+}
+
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+pub enum Feature {
+    Tree,
+    Cactus,
+}
+
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Vegetation {
+    pub feature: Feature,
+    // Scale of the noise plants are clustered by, smaller gives larger clusters.
+    pub scale: f64,
+    // From 0 to 1, higher is rarer.
+    pub rarity: f32,
+    // Smallest and largest height, the largest excluded.
+    pub height: (usize, usize),
+}
+
+
+pub struct BiomeRegistry {
+    // Blocks around a border over which neighbouring biomes are blended.
+    blend_radius: i32,
+    biomes: Vec<BiomeProperties>,
+}
+
+
+#[derive(Deserialize)]
+struct RegistryDefinition {
+    blend_radius: i32,
+    biomes: Vec<BiomeDefinition>,
+}
+
+
+#[derive(Deserialize)]
+struct BiomeDefinition {
+    name: String,
+    #[serde(default)]
+    temperature: ClimateRange,
+    #[serde(default)]
+    humidity: ClimateRange,
+    surface: String,
+    subsurface: String,
+    underwater: String,
+    #[serde(default)]
+    vegetation: Vec<Vegetation>,
+    #[serde(default = "default_tint")]
+    grass_tint: [f32; 3],
+    #[serde(default = "default_tint")]
+    foliage_tint: [f32; 3],
+    #[serde(default)]
+    elevation: f32,
+    #[serde(default = "default_relief")]
+    relief: f32,
+}
+
+
+#[derive(Deserialize, Default)]
+struct ClimateRange {
+    min: Option<f32>,
+    max: Option<f32>,
+}
+
+
+impl ClimateRange {
+    fn bounds(&self) -> (f32, f32) {
+        (self.min.unwrap_or(f32::NEG_INFINITY), self.max.unwrap_or(f32::INFINITY))
+    }
+}
+
+
+fn default_tint() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+
+fn default_relief() -> f32 {
+    1.0
+}
+
+
+pub fn registry() -> &'static BiomeRegistry {
+    REGISTRY.get().expect("[E] Biome registry used before it was loaded!")
+}
+
+
+pub fn load_biome_registry(path: impl AsRef<Path>) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let registry = parse_biome_registry(&text)?;
+
+    REGISTRY.set(registry).map_err(|_| io::Error::new(ErrorKind::AlreadyExists, "biome registry is already loaded"))
+}
+
+
+fn parse_biome_registry(text: &str) -> io::Result<BiomeRegistry> {
+    let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+    let definition: RegistryDefinition = ron::from_str(text).map_err(|e| invalid(e.to_string()))?;
+
+    if definition.biomes.is_empty() || definition.biomes.len() > u8::MAX as usize {
+        return Err(invalid(format!("there must be 1 to {} biomes", u8::MAX)));
+    }
+    if definition.blend_radius < 0 {
+        return Err(invalid("the blend radius can't be negative".to_string()));
+    }
+
+    let mut registry = BiomeRegistry { blend_radius: definition.blend_radius, biomes: vec![] };
+    let mut names = HashSet::new();
+
+    for definition in definition.biomes {
+        if !names.insert(definition.name.clone()) {
+            return Err(invalid(format!("biome {} is defined twice", definition.name)));
+        }
+
+        let block = |name: &String| BlockType::from_name(name)
+            .ok_or_else(|| invalid(format!("biome {} uses missing block {}", definition.name, name)));
+
+        for vegetation in &definition.vegetation {
+            if vegetation.height.0 >= vegetation.height.1 {
+                return Err(invalid(format!("biome {} has a {:?} with an empty height range", definition.name, vegetation.feature)));
+            }
+        }
+
+        registry.biomes.push(BiomeProperties {
+            surface: block(&definition.surface)?,
+            subsurface: block(&definition.subsurface)?,
+            underwater: block(&definition.underwater)?,
+            name: definition.name,
+            temperature: definition.temperature.bounds(),
+            humidity: definition.humidity.bounds(),
+            vegetation: definition.vegetation,
+            grass_tint: definition.grass_tint,
+            foliage_tint: definition.foliage_tint,
+            elevation: definition.elevation,
+            relief: definition.relief,
+        });
+    }
+
+    Ok(registry)
+}
+
+
+pub fn temperature_at(perlin: &SeededPerlin, x: i32, z: i32) -> f32 {
+    perlin.temperature_noise.get([x as f64 * 0.001, z as f64 * 0.001]) as f32 * 10.0
+}
+
+
+pub fn humidity_at(perlin: &SeededPerlin, x: i32, z: i32) -> f32 {
+    perlin.moisture_noise.get([x as f64 * 0.001, z as f64 * 0.001]) as f32 * 10.0
+}
+
+
+// Biome of the column at world x, z. Biomes are tried in registry order and the first one
+// whose climate fits is picked, or the last one if none does.
+pub fn biome_at(perlin: &SeededPerlin, x: i32, z: i32) -> Biome {
+    let temperature = temperature_at(perlin, x, z);
+    let humidity = humidity_at(perlin, x, z);
+    let biomes = &registry().biomes;
+
+    let index = biomes.iter().position(|biome| {
+        (biome.temperature.0 ..= biome.temperature.1).contains(&temperature)
+            && (biome.humidity.0 ..= biome.humidity.1).contains(&humidity)
+    });
+
+    Biome(index.unwrap_or(biomes.len() - 1) as u8)
+}
+
+
+// Share of the biomes around a column, taken from the biomes at the corners of the lattice
+// cell the column is in. Cells are two blend radii wide, so values fade from one biome to
+// the next over a blend radius on either side of their border.
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeBlend {
+    corners: [(Biome, f32); 4],
+}
+
+
+impl BiomeBlend {
+    // Share of the biome in the column, from 0 to 1.
+    pub fn weight(&self, biome: Biome) -> f32 {
+        self.corners.iter().filter(|(corner, _)| *corner == biome).map(|(_, weight)| weight).sum()
+    }
+
+
+    // Biomes with a share in the column, largest share first.
+    pub fn shares(&self) -> Vec<(Biome, f32)> {
+        let mut shares: Vec<(Biome, f32)> = vec![];
+        for (biome, _) in self.corners {
+            if !shares.iter().any(|(other, _)| *other == biome) && self.weight(biome) > 0.0 {
+                shares.push((biome, self.weight(biome)));
+            }
+        }
+
+        shares.sort_by(|a, b| b.1.total_cmp(&a.1));
+        shares
+    }
+
+
+    // Picks biomes in proportion to their share for a value from 0 to 1, so columns along
+    // a border mix the blocks of both sides.
+    pub fn pick(&self, value: f32) -> Biome {
+        let mut total = 0.0;
+        for (biome, weight) in self.corners {
+            total += weight;
+            if value < total {
+                return biome;
+            }
+        }
+
+        self.corners[3].0
+    }
+
+
+    // Within a biome the value is kept exact, so faces of the same tint can still be merged.
+    pub fn mix(&self, value: impl Fn(&BiomeProperties) -> f32) -> f32 {
+        let values = self.corners.map(|(biome, _)| value(biome.properties()));
+        if values.iter().all(|other| *other == values[0]) {
+            return values[0];
+        }
+
+        values.iter().zip(self.corners).map(|(value, (_, weight))| value * weight).sum()
+    }
+
+
+    pub fn mix_color(&self, color: impl Fn(&BiomeProperties) -> [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|channel| self.mix(|biome| color(biome)[channel]))
+    }
+}
+
+
+pub fn blend_at(perlin: &SeededPerlin, x: i32, z: i32) -> BiomeBlend {
+    let spacing = registry().blend_radius * 2;
+    if spacing == 0 {
+        return BiomeBlend { corners: [(biome_at(perlin, x, z), 1.0), (Biome(0), 0.0), (Biome(0), 0.0), (Biome(0), 0.0)] };
+    }
+
+    let (cell_x, cell_z) = (x.div_euclid(spacing), z.div_euclid(spacing));
+    let (fx, fz) = (x.rem_euclid(spacing) as f32 / spacing as f32, z.rem_euclid(spacing) as f32 / spacing as f32);
+
+    let corner = |dx: i32, dz: i32, weight: f32| (biome_at(perlin, (cell_x + dx) * spacing, (cell_z + dz) * spacing), weight);
+
+    BiomeBlend { corners: [
+        corner(0, 0, (1.0 - fx) * (1.0 - fz)),
+        corner(1, 0, fx * (1.0 - fz)),
+        corner(0, 1, (1.0 - fx) * fz),
+        corner(1, 1, fx * fz),
+    ] }
+}
+
+
+// Blended grass and foliage tints of every column of a chunk, by [z][x], for the mesher.
+pub struct ChunkTints {
+    grass: [[[f32; 3]; CHUNK_WIDTH]; CHUNK_WIDTH],
+    foliage: [[[f32; 3]; CHUNK_WIDTH]; CHUNK_WIDTH],
+}
+
+
+impl ChunkTints {
+    pub fn new(perlin: &SeededPerlin, chunk_pos: (i32, i32)) -> Self {
+        let mut tints = ChunkTints { grass: [[[1.0; 3]; CHUNK_WIDTH]; CHUNK_WIDTH], foliage: [[[1.0; 3]; CHUNK_WIDTH]; CHUNK_WIDTH] };
+
+        for z in 0..CHUNK_WIDTH {
+            for x in 0..CHUNK_WIDTH {
+                let blend = blend_at(perlin, chunk_pos.0 * CHUNK_WIDTH as i32 + x as i32, chunk_pos.1 * CHUNK_WIDTH as i32 + z as i32);
+                tints.grass[z][x] = blend.mix_color(|biome| biome.grass_tint);
+                tints.foliage[z][x] = blend.mix_color(|biome| biome.foliage_tint);
+            }
+        }
+
+        tints
+    }
+
+
+    // Colour the face of a block in the column is multiplied by. Only the top of grass,
+    // where the texture is all grass, and leaves are tinted.
+    pub fn tint(&self, block: BlockType, face: Face, x: usize, z: usize) -> [f32; 3] {
+        match (block, face) {
+            (BlockType::GRASS, Face::Top) => self.grass[z][x],
+            (BlockType::LEAVES, _) => self.foliage[z][x],
+            _ => [1.0; 3],
+        }
+    }
+}
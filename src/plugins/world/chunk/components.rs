@@ -1,127 +1,164 @@
-use bevy::prelude::*;
+use super::registry::{registry, BlockProperties};
+
+
+// Id of a block in the block registry, see assets/blocks.ron, in the low 12 bits, and
+// block specific state in the high 4 bits, such as how far water has flowed.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct BlockType(u16);
 
-#[derive(PartialEq, Copy, Clone, Debug)]
-pub enum BlockType {
-    Air,
-    Dirt,
-    Grass,
-    Stone,
-    Sand,
-    Water,
-    WoodLog,
-    Leaves,
-    BedRock,
-    OreStoneGold,
-    Cactus,
-}
+
+pub const MAX_BLOCK_ID: u16 = (1 << STATE_SHIFT) - 1;
+const STATE_SHIFT: u32 = 12;
 
 impl BlockType {
+    pub const AIR: BlockType = BlockType(0);
+    pub const DIRT: BlockType = BlockType(1);
+    pub const GRASS: BlockType = BlockType(2);
+    pub const STONE: BlockType = BlockType(3);
+    pub const SAND: BlockType = BlockType(4);
+    pub const WATER: BlockType = BlockType(5);
+    pub const WOOD_LOG: BlockType = BlockType(6);
+    pub const LEAVES: BlockType = BlockType(7);
+    pub const BEDROCK: BlockType = BlockType(8);
+    pub const CACTUS: BlockType = BlockType(10);
+
+    // Blocks the code refers to, and the names the registry has to give them.
+    pub const BUILTIN: [(BlockType, &'static str); 10] = [
+        (BlockType::AIR, "air"),
+        (BlockType::DIRT, "dirt"),
+        (BlockType::GRASS, "grass"),
+        (BlockType::STONE, "stone"),
+        (BlockType::SAND, "sand"),
+        (BlockType::WATER, "water"),
+        (BlockType::WOOD_LOG, "wood_log"),
+        (BlockType::LEAVES, "leaves"),
+        (BlockType::BEDROCK, "bedrock"),
+        (BlockType::CACTUS, "cactus"),
+    ];
+
+    pub fn id(&self) -> u16 {
+        self.0 & MAX_BLOCK_ID
+    }
+
+    pub fn from_id(id: u16) -> Option<BlockType> {
+        registry().get(id).map(|_| BlockType(id))
+    }
+
+    pub fn from_name(name: &str) -> Option<BlockType> {
+        registry().find(name).map(BlockType)
+    }
+
+    // Id and state together, as blocks are saved.
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+
+    pub fn from_raw(raw: u16) -> Option<BlockType> {
+        BlockType::from_id(raw & MAX_BLOCK_ID).map(|_| BlockType(raw))
+    }
+
+    pub fn state(&self) -> u8 {
+        (self.0 >> STATE_SHIFT) as u8
+    }
+
+    pub fn with_state(&self, state: u8) -> BlockType {
+        BlockType(self.id() | (state as u16 & 0xF) << STATE_SHIFT)
+    }
+
+    // The block without its state, for comparing kinds of blocks.
+    pub fn base(&self) -> BlockType {
+        BlockType(self.id())
+    }
+
+    pub fn properties(&self) -> &'static BlockProperties {
+        registry().get(self.id()).expect("[E] Block missing from the registry!")
+    }
+
     pub fn is_transparent(&self) -> bool {
+        self.properties().transparent
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.properties().solid
+    }
+
+    pub fn is_breakable(&self) -> bool {
+        self.properties().breakable
+    }
+
+    pub fn emission(&self) -> u8 {
+        self.properties().emission
+    }
+
+    pub fn falls(&self) -> bool {
+        self.properties().falls
+    }
+
+    pub fn tile(&self, face: Face) -> [f32; 4] {
+        self.properties().tiles[face as usize]
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Face {
+    Right,
+    Left,
+    Back,
+    Front,
+    Bottom,
+    Top,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::Right, Face::Left, Face::Back, Face::Front, Face::Bottom, Face::Top];
+
+    pub fn normal(&self) -> (i32, i32, i32) {
         match self {
-            BlockType::Air => true,
-            BlockType::Water => true,
-            _ => false,
+            Face::Right => (1, 0, 0),
+            Face::Left => (-1, 0, 0),
+            Face::Back => (0, 0, -1),
+            Face::Front => (0, 0, 1),
+            Face::Bottom => (0, -1, 0),
+            Face::Top => (0, 1, 0),
         }
     }
 
-    pub fn uvs(&self) -> BlockFaces {
+    // Corners of the face on the unit cube, in the order its uvs and AO values are given.
+    pub fn corners(&self) -> [[f32; 3]; 4] {
         match self {
-            BlockType::Air => BlockFaces::new(),
-            BlockType::Dirt => BlockFaces {
-                left: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-                right: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-                front: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-                back: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-                top: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-                bottom: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-            },
-            BlockType::Grass => BlockFaces {
-                left: vec![Vec2::new(0.2, 0.0), Vec2::new(0.1, 0.0), Vec2::new(0.1, 0.1), Vec2::new(0.2, 0.1)],
-                right: vec![Vec2::new(0.2, 0.0), Vec2::new(0.1, 0.0), Vec2::new(0.1, 0.1), Vec2::new(0.2, 0.1)],
-                front: vec![Vec2::new(0.2, 0.0), Vec2::new(0.1, 0.0), Vec2::new(0.1, 0.1), Vec2::new(0.2, 0.1)],
-                back: vec![Vec2::new(0.2, 0.0), Vec2::new(0.1, 0.0), Vec2::new(0.1, 0.1), Vec2::new(0.2, 0.1)],
-                top: vec![Vec2::new(0.3, 0.0), Vec2::new(0.2, 0.0), Vec2::new(0.2, 0.1), Vec2::new(0.3, 0.1)],
-                bottom: vec![Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.1), Vec2::new(0.1, 0.1)],
-            },
-            BlockType::Stone => BlockFaces {
-                left: vec![Vec2::new(0.4, 0.0), Vec2::new(0.3, 0.0), Vec2::new(0.3, 0.1), Vec2::new(0.4, 0.1)],
-                right: vec![Vec2::new(0.4, 0.0), Vec2::new(0.3, 0.0), Vec2::new(0.3, 0.1), Vec2::new(0.4, 0.1)],
-                front: vec![Vec2::new(0.4, 0.0), Vec2::new(0.3, 0.0), Vec2::new(0.3, 0.1), Vec2::new(0.4, 0.1)],
-                back: vec![Vec2::new(0.4, 0.0), Vec2::new(0.3, 0.0), Vec2::new(0.3, 0.1), Vec2::new(0.4, 0.1)],
-                top: vec![Vec2::new(0.4, 0.0), Vec2::new(0.3, 0.0), Vec2::new(0.3, 0.1), Vec2::new(0.4, 0.1)],
-                bottom: vec![Vec2::new(0.4, 0.0), Vec2::new(0.3, 0.0), Vec2::new(0.3, 0.1), Vec2::new(0.4, 0.1)],
-            },
-            BlockType::Sand => BlockFaces {
-                left: vec![Vec2::new(0.5, 0.0), Vec2::new(0.4, 0.0), Vec2::new(0.4, 0.1), Vec2::new(0.5, 0.1)],
-                right: vec![Vec2::new(0.5, 0.0), Vec2::new(0.4, 0.0), Vec2::new(0.4, 0.1), Vec2::new(0.5, 0.1)],
-                front: vec![Vec2::new(0.5, 0.0), Vec2::new(0.4, 0.0), Vec2::new(0.4, 0.1), Vec2::new(0.5, 0.1)],
-                back: vec![Vec2::new(0.5, 0.0), Vec2::new(0.4, 0.0), Vec2::new(0.4, 0.1), Vec2::new(0.5, 0.1)],
-                top: vec![Vec2::new(0.5, 0.0), Vec2::new(0.4, 0.0), Vec2::new(0.4, 0.1), Vec2::new(0.5, 0.1)],
-                bottom: vec![Vec2::new(0.5, 0.0), Vec2::new(0.4, 0.0), Vec2::new(0.4, 0.1), Vec2::new(0.5, 0.1)],
-            },
-            BlockType::Water => BlockFaces {
-                left: vec![Vec2::new(0.6, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 0.1), Vec2::new(0.6, 0.1)],
-                right: vec![Vec2::new(0.6, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 0.1), Vec2::new(0.6, 0.1)],
-                front: vec![Vec2::new(0.6, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 0.1), Vec2::new(0.6, 0.1)],
-                back: vec![Vec2::new(0.6, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 0.1), Vec2::new(0.6, 0.1)],
-                top: vec![Vec2::new(0.6, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 0.1), Vec2::new(0.6, 0.1)],
-                bottom: vec![Vec2::new(0.6, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 0.1), Vec2::new(0.6, 0.1)],
-            },
-            BlockType::WoodLog => BlockFaces {
-                left: vec![Vec2::new(0.8, 0.0), Vec2::new(0.7, 0.0), Vec2::new(0.7, 0.1), Vec2::new(0.8, 0.1)],
-                right: vec![Vec2::new(0.8, 0.0), Vec2::new(0.7, 0.0), Vec2::new(0.7, 0.1), Vec2::new(0.8, 0.1)],
-                front: vec![Vec2::new(0.8, 0.0), Vec2::new(0.7, 0.0), Vec2::new(0.7, 0.1), Vec2::new(0.8, 0.1)],
-                back: vec![Vec2::new(0.8, 0.0), Vec2::new(0.7, 0.0), Vec2::new(0.7, 0.1), Vec2::new(0.8, 0.1)],
-                top: vec![Vec2::new(0.7, 0.0), Vec2::new(0.6, 0.0), Vec2::new(0.6, 0.1), Vec2::new(0.7, 0.1)],
-                bottom: vec![Vec2::new(0.7, 0.0), Vec2::new(0.6, 0.0), Vec2::new(0.6, 0.1), Vec2::new(0.7, 0.1)],
-            },
-            BlockType::Leaves => BlockFaces {
-                left: vec![Vec2::new(0.9, 0.0), Vec2::new(0.8, 0.0), Vec2::new(0.8, 0.1), Vec2::new(0.9, 0.1)],
-                right: vec![Vec2::new(0.9, 0.0), Vec2::new(0.8, 0.0), Vec2::new(0.8, 0.1), Vec2::new(0.9, 0.1)],
-                front: vec![Vec2::new(0.9, 0.0), Vec2::new(0.8, 0.0), Vec2::new(0.8, 0.1), Vec2::new(0.9, 0.1)],
-                back: vec![Vec2::new(0.9, 0.0), Vec2::new(0.8, 0.0), Vec2::new(0.8, 0.1), Vec2::new(0.9, 0.1)],
-                top: vec![Vec2::new(0.9, 0.0), Vec2::new(0.8, 0.0), Vec2::new(0.8, 0.1), Vec2::new(0.9, 0.1)],
-                bottom: vec![Vec2::new(0.9, 0.0), Vec2::new(0.8, 0.0), Vec2::new(0.8, 0.1), Vec2::new(0.9, 0.1)],
-            },
-            BlockType::BedRock => BlockFaces {
-                left: vec![Vec2::new(0.1, 0.1), Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2), Vec2::new(0.1, 0.2)],
-                right: vec![Vec2::new(0.1, 0.1), Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2), Vec2::new(0.1, 0.2)],
-                front: vec![Vec2::new(0.1, 0.1), Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2), Vec2::new(0.1, 0.2)],
-                back: vec![Vec2::new(0.1, 0.1), Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2), Vec2::new(0.1, 0.2)],
-                top: vec![Vec2::new(0.1, 0.1), Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2), Vec2::new(0.1, 0.2)],
-                bottom: vec![Vec2::new(0.1, 0.1), Vec2::new(0.0, 0.1), Vec2::new(0.0, 0.2), Vec2::new(0.1, 0.2)],
-            },
-            BlockType::OreStoneGold => BlockFaces {
-                left: vec![Vec2::new(0.2, 0.1), Vec2::new(0.1, 0.1), Vec2::new(0.1, 0.2), Vec2::new(0.2, 0.2)],
-                right: vec![Vec2::new(0.2, 0.1), Vec2::new(0.1, 0.1), Vec2::new(0.1, 0.2), Vec2::new(0.2, 0.2)],
-                front: vec![Vec2::new(0.2, 0.1), Vec2::new(0.1, 0.1), Vec2::new(0.1, 0.2), Vec2::new(0.2, 0.2)],
-                back: vec![Vec2::new(0.2, 0.1), Vec2::new(0.1, 0.1), Vec2::new(0.1, 0.2), Vec2::new(0.2, 0.2)],
-                top: vec![Vec2::new(0.2, 0.1), Vec2::new(0.1, 0.1), Vec2::new(0.1, 0.2), Vec2::new(0.2, 0.2)],
-                bottom: vec![Vec2::new(0.2, 0.1), Vec2::new(0.1, 0.1), Vec2::new(0.1, 0.2), Vec2::new(0.2, 0.2)],
-            }, 
-            BlockType::Cactus => BlockFaces {
-                left: vec![Vec2::new(0.8, 0.1), Vec2::new(0.7, 0.1), Vec2::new(0.7, 0.2), Vec2::new(0.8, 0.2)],
-                right: vec![Vec2::new(0.8, 0.1), Vec2::new(0.7, 0.1), Vec2::new(0.7, 0.2), Vec2::new(0.8, 0.2)],
-                front: vec![Vec2::new(0.8, 0.1), Vec2::new(0.7, 0.1), Vec2::new(0.7, 0.2), Vec2::new(0.8, 0.2)],
-                back: vec![Vec2::new(0.8, 0.1), Vec2::new(0.7, 0.1), Vec2::new(0.7, 0.2), Vec2::new(0.8, 0.2)],
-                top: vec![Vec2::new(0.7, 0.1), Vec2::new(0.6, 0.1), Vec2::new(0.6, 0.2), Vec2::new(0.7, 0.2)],
-                bottom: vec![Vec2::new(0.7, 0.1), Vec2::new(0.6, 0.1), Vec2::new(0.6, 0.2), Vec2::new(0.7, 0.2)],
-            },
+            Face::Right => [[1.0, 1.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 1.0], [1.0, 0.0, 0.0]],
+            Face::Left => [[0.0, 1.0, 1.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            Face::Back => [[0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 0.0]],
+            Face::Front => [[1.0, 1.0, 1.0], [0.0, 1.0, 1.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]],
+            Face::Bottom => [[0.0, 0.0, 1.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0]],
+            Face::Top => [[1.0, 1.0, 1.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 1.0]],
         }
     }
-}
 
-pub struct BlockFaces {
-    pub left: Vec<Vec2>,
-    pub right: Vec<Vec2>,
-    pub front: Vec<Vec2>,
-    pub back: Vec<Vec2>,
-    pub top: Vec<Vec2>,
-    pub bottom: Vec<Vec2>,
-}
+    // The texture's u runs along the first edge of the face and v along the second.
+    pub fn u_axis(&self) -> usize {
+        match self {
+            Face::Right | Face::Left | Face::Bottom | Face::Top => 2,
+            Face::Back | Face::Front => 0,
+        }
+    }
 
-impl BlockFaces {
-    fn new() -> Self {
-        BlockFaces { left: vec![], right: vec![], front: vec![], back: vec![], top: vec![], bottom: vec![] }
+    pub fn v_axis(&self) -> usize {
+        match self {
+            Face::Right | Face::Left | Face::Back | Face::Front => 1,
+            Face::Bottom | Face::Top => 0,
+        }
+    }
+
+    // The eight blocks around the face, going around it for ambient occlusion.
+    pub fn ao_neighbours(&self) -> [(i32, i32, i32); 8] {
+        match self {
+            Face::Right => [(1, 0, -1), (1, -1, -1), (1, -1, 0), (1, -1, 1), (1, 0, 1), (1, 1, 1), (1, 1, 0), (1, 1, -1)],
+            Face::Left => [(-1, 0, 1), (-1, -1, 1), (-1, -1, 0), (-1, -1, -1), (-1, 0, -1), (-1, 1, -1), (-1, 1, 0), (-1, 1, 1)],
+            Face::Back => [(-1, 0, -1), (-1, -1, -1), (0, -1, -1), (1, -1, -1), (1, 0, -1), (1, 1, -1), (0, 1, -1), (-1, 1, -1)],
+            Face::Front => [(1, 0, 1), (1, -1, 1), (0, -1, 1), (-1, -1, 1), (-1, 0, 1), (-1, 1, 1), (0, 1, 1), (1, 1, 1)],
+            Face::Bottom => [(-1, -1, 0), (-1, -1, 1), (0, -1, 1), (1, -1, 1), (1, -1, 0), (1, -1, -1), (0, -1, -1), (-1, -1, -1)],
+            Face::Top => [(0, 1, 1), (-1, 1, 1), (-1, 1, 0), (-1, 1, -1), (0, 1, -1), (1, 1, -1), (1, 1, 0), (1, 1, 1)],
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,385 @@
+use crate::{CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_VOL, SECTION_HEIGHT, SECTION_COUNT, SECTION_VOL};
+
+use super::components::BlockType;
+
+
+// Block storage for one chunk column, split into SECTION_HEIGHT high sections
+// stacked from the bottom of the world.
+#[derive(Clone, Debug)]
+pub struct ChunkData {
+    sections: Vec<Section>,
+}
+
+
+impl ChunkData {
+    pub fn new(block: BlockType) -> Self {
+        ChunkData { sections: vec![Section::new(block); SECTION_COUNT] }
+    }
+
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
+        self.sections[y / SECTION_HEIGHT].get(x, y % SECTION_HEIGHT, z)
+    }
+
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockType) {
+        self.sections[y / SECTION_HEIGHT].set(x, y % SECTION_HEIGHT, z, block);
+    }
+
+
+    pub fn get_index(&self, index: usize) -> BlockType {
+        let (x, y, z) = coords(index);
+        self.get(x, y, z)
+    }
+
+
+    pub fn set_index(&mut self, index: usize, block: BlockType) {
+        let (x, y, z) = coords(index);
+        self.set(x, y, z, block);
+    }
+
+
+    // Sky light in the high nibble, block light in the low one.
+    pub fn light(&self, x: usize, y: usize, z: usize) -> u8 {
+        self.sections[y / SECTION_HEIGHT].light(x, y % SECTION_HEIGHT, z)
+    }
+
+
+    pub fn set_light(&mut self, x: usize, y: usize, z: usize, light: u8) {
+        self.sections[y / SECTION_HEIGHT].set_light(x, y % SECTION_HEIGHT, z, light);
+    }
+
+
+    pub fn section(&self, section: usize) -> &Section {
+        &self.sections[section]
+    }
+
+
+    pub fn section_mut(&mut self, section: usize) -> &mut Section {
+        &mut self.sections[section]
+    }
+
+
+    pub fn blocks(&self) -> impl Iterator<Item = BlockType> + '_ {
+        (0 .. CHUNK_VOL).map(|index| self.get_index(index))
+    }
+
+
+    pub fn compact(&mut self) {
+        for section in self.sections.iter_mut() {
+            section.compact();
+        }
+    }
+}
+
+
+// One SECTION_HEIGHT high slice of a chunk. Every distinct block in the section gets a
+// palette entry and the cells store bit-packed indices into the palette, so a section with
+// few block types takes a few bits per cell. A section made of a single block, like the
+// air above the terrain, stores no indices at all. Light is kept the same way: a section
+// with the same light everywhere, like open sky or solid rock, stores just that value.
+#[derive(Clone, Debug)]
+pub struct Section {
+    palette: Vec<BlockType>,
+    bits: usize,
+    words: Vec<u64>,
+    light: Vec<u8>,
+    uniform_light: u8,
+}
+
+
+impl Section {
+    pub fn new(block: BlockType) -> Self {
+        Section { palette: vec![block], bits: 0, words: vec![], light: vec![], uniform_light: 0 }
+    }
+
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
+        if self.bits == 0 {
+            return self.palette[0];
+        }
+
+        let index = section_index(x, y, z);
+        let per_word = 64 / self.bits;
+        let word = self.words[index / per_word];
+        let shift = (index % per_word) * self.bits;
+
+        self.palette[((word >> shift) & mask(self.bits)) as usize]
+    }
+
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockType) {
+        let palette_index = match self.palette.iter().position(|b| *b == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(block);
+                let bits = bits_for(self.palette.len());
+                if bits > self.bits {
+                    self.repack(bits);
+                }
+                self.palette.len() - 1
+            }
+        };
+
+        // Still uniform, and the block is the one the section is made of.
+        if self.bits == 0 {
+            return;
+        }
+
+        let index = section_index(x, y, z);
+        let per_word = 64 / self.bits;
+        let shift = (index % per_word) * self.bits;
+        let word = &mut self.words[index / per_word];
+
+        *word = (*word & !(mask(self.bits) << shift)) | ((palette_index as u64) << shift);
+    }
+
+
+    pub fn uniform_block(&self) -> Option<BlockType> {
+        if self.bits == 0 {
+            return Some(self.palette[0]);
+        }
+        None
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.uniform_block() == Some(BlockType::AIR)
+    }
+
+
+    pub fn palette(&self) -> &[BlockType] {
+        &self.palette
+    }
+
+
+    pub fn light(&self, x: usize, y: usize, z: usize) -> u8 {
+        if self.light.is_empty() {
+            return self.uniform_light;
+        }
+        self.light[section_index(x, y, z)]
+    }
+
+
+    pub fn set_light(&mut self, x: usize, y: usize, z: usize, light: u8) {
+        if self.light.is_empty() {
+            if light == self.uniform_light {
+                return;
+            }
+            self.light = vec![self.uniform_light; SECTION_VOL];
+        }
+        self.light[section_index(x, y, z)] = light;
+    }
+
+
+    pub fn uniform_light(&self) -> Option<u8> {
+        if self.light.is_empty() {
+            return Some(self.uniform_light);
+        }
+        None
+    }
+
+
+    pub fn fill_light(&mut self, light: u8) {
+        self.light = vec![];
+        self.uniform_light = light;
+    }
+
+
+    // Drop palette entries that are no longer used and shrink the indices to match.
+    pub fn compact(&mut self) {
+        if self.bits == 0 {
+            return;
+        }
+
+        let mut compacted = Section::new(self.get(0, 0, 0));
+        for z in 0 .. CHUNK_WIDTH {
+            for y in 0 .. SECTION_HEIGHT {
+                for x in 0 .. CHUNK_WIDTH {
+                    compacted.set(x, y, z, self.get(x, y, z));
+                }
+            }
+        }
+
+        compacted.light = std::mem::take(&mut self.light);
+        compacted.uniform_light = self.uniform_light;
+        *self = compacted;
+    }
+
+
+    fn repack(&mut self, bits: usize) {
+        let old = std::mem::replace(self, Section {
+            palette: vec![],
+            bits,
+            words: vec![0; SECTION_VOL.div_ceil(64 / bits)],
+            light: vec![],
+            uniform_light: 0,
+        });
+
+        let per_word = 64 / bits;
+        for index in 0 .. SECTION_VOL {
+            let palette_index = match old.bits {
+                0 => 0,
+                _ => {
+                    let old_per_word = 64 / old.bits;
+                    (old.words[index / old_per_word] >> ((index % old_per_word) * old.bits)) & mask(old.bits)
+                }
+            };
+            self.words[index / per_word] |= palette_index << ((index % per_word) * bits);
+        }
+
+        self.palette = old.palette;
+        self.light = old.light;
+        self.uniform_light = old.uniform_light;
+    }
+}
+
+
+fn coords(index: usize) -> (usize, usize, usize) {
+    (index % CHUNK_WIDTH, (index / CHUNK_WIDTH) % CHUNK_HEIGHT, index / (CHUNK_WIDTH * CHUNK_HEIGHT))
+}
+
+
+fn section_index(x: usize, y: usize, z: usize) -> usize {
+    x + y * CHUNK_WIDTH + z * CHUNK_WIDTH * SECTION_HEIGHT
+}
+
+
+// Index widths are kept to powers of two so that no index straddles two words.
+fn bits_for(palette_len: usize) -> usize {
+    match palette_len {
+        0 ..= 1 => 0,
+        2 => 1,
+        3 ..= 4 => 2,
+        5 ..= 16 => 4,
+        17 ..= 256 => 8,
+        _ => 16,
+    }
+}
+
+
+fn mask(bits: usize) -> u64 {
+    (1 << bits) - 1
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    // Blocks that don't need the registry: water with each of its flow states, then a few builtins.
+    fn distinct_blocks() -> Vec<BlockType> {
+        (0..16).map(|state| BlockType::WATER.with_state(state))
+            .chain([BlockType::STONE, BlockType::DIRT, BlockType::SAND, BlockType::GRASS])
+            .collect()
+    }
+
+
+    fn cell(index: usize) -> (usize, usize, usize) {
+        (index % CHUNK_WIDTH, (index / CHUNK_WIDTH) % SECTION_HEIGHT, index / (CHUNK_WIDTH * SECTION_HEIGHT))
+    }
+
+
+    #[test]
+    fn blocks_survive_the_palette_growing() {
+        let blocks = distinct_blocks();
+        let mut section = Section::new(BlockType::AIR);
+
+        for (i, block) in blocks.iter().enumerate() {
+            let (x, y, z) = cell(i * 7);
+            section.set(x, y, z, *block);
+
+            // Air and the blocks set so far.
+            let expected_bits = match i + 2 {
+                2 => 1,
+                3..=4 => 2,
+                5..=16 => 4,
+                _ => 8,
+            };
+            assert_eq!(section.bits, expected_bits, "after {} blocks", i + 1);
+
+            for (j, block) in blocks[..=i].iter().enumerate() {
+                let (x, y, z) = cell(j * 7);
+                assert_eq!(section.get(x, y, z), *block, "block {} lost going to {} bits", j, section.bits);
+            }
+            let (x, y, z) = cell(i * 7 + 1);
+            assert_eq!(section.get(x, y, z), BlockType::AIR);
+        }
+    }
+
+
+    #[test]
+    fn compacting_drops_unused_blocks() {
+        let blocks = distinct_blocks();
+        let mut section = Section::new(BlockType::AIR);
+        for (i, block) in blocks.iter().enumerate() {
+            let (x, y, z) = cell(i);
+            section.set(x, y, z, *block);
+        }
+
+        // Only stone and air are left.
+        for i in 1..blocks.len() {
+            let (x, y, z) = cell(i);
+            section.set(x, y, z, BlockType::AIR);
+        }
+        section.set(0, 0, 0, BlockType::STONE);
+        section.compact();
+        assert_eq!(section.palette().len(), 2);
+        assert_eq!(section.get(0, 0, 0), BlockType::STONE);
+        assert_eq!(section.get(1, 0, 0), BlockType::AIR);
+
+        // A section of a single block goes back to storing no indices.
+        section.set(0, 0, 0, BlockType::AIR);
+        section.compact();
+        assert_eq!(section.uniform_block(), Some(BlockType::AIR));
+        assert!(section.is_empty());
+        assert!(section.words.is_empty());
+    }
+
+
+    #[test]
+    fn light_is_uniform_until_a_cell_differs() {
+        let mut section = Section::new(BlockType::AIR);
+        section.fill_light(0xF0);
+        assert_eq!(section.uniform_light(), Some(0xF0));
+
+        // Setting the light a section already has everywhere keeps it uniform.
+        section.set_light(3, 4, 5, 0xF0);
+        assert_eq!(section.uniform_light(), Some(0xF0));
+
+        section.set_light(3, 4, 5, 0x3C);
+        assert_eq!(section.uniform_light(), None);
+        assert_eq!(section.light(3, 4, 5), 0x3C);
+        assert_eq!(section.light(0, 0, 0), 0xF0);
+
+        // Light is kept when the blocks are repacked.
+        for (i, block) in distinct_blocks().iter().enumerate() {
+            let (x, y, z) = cell(i);
+            section.set(x, y, z, *block);
+        }
+        assert_eq!(section.light(3, 4, 5), 0x3C);
+        assert_eq!(section.light(0, 0, 0), 0xF0);
+
+        section.fill_light(0);
+        assert_eq!(section.uniform_light(), Some(0));
+        assert_eq!(section.light(3, 4, 5), 0);
+    }
+
+
+    #[test]
+    fn chunk_cells_map_to_their_section() {
+        let mut blocks = ChunkData::new(BlockType::AIR);
+        blocks.set(1, SECTION_HEIGHT - 1, 2, BlockType::STONE);
+        blocks.set(1, SECTION_HEIGHT, 2, BlockType::DIRT);
+        blocks.set_light(1, SECTION_HEIGHT, 2, 0x0E);
+
+        assert_eq!(blocks.section(0).get(1, SECTION_HEIGHT - 1, 2), BlockType::STONE);
+        assert_eq!(blocks.section(1).get(1, 0, 2), BlockType::DIRT);
+        assert_eq!(blocks.section(1).light(1, 0, 2), 0x0E);
+        assert_eq!(blocks.section(0).uniform_light(), Some(0));
+
+        let index = blocks.blocks().position(|block| block == BlockType::DIRT).unwrap();
+        assert_eq!(coords(index), (1, SECTION_HEIGHT, 2));
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::CHUNK_HEIGHT;
+
+use super::{components::BlockType, data::ChunkData, light::{Position, block_at, offset}};
+
+
+// Water state: 0 is a source block, 1 to MAX_FLOW water that has flowed that many blocks
+// sideways from one, and FALLING water that pours down from the block above.
+pub const MAX_FLOW: u8 = 7;
+const FALLING: u8 = 8;
+
+const SIDES: [Position; 4] = [(1, 0, 0), (-1, 0, 0), (0, 0, 1), (0, 0, -1)];
+const UP: Position = (0, 1, 0);
+const DOWN: Position = (0, -1, 0);
+
+
+pub fn is_water(block: BlockType) -> bool {
+    block.base() == BlockType::WATER
+}
+
+
+pub fn is_source(block: BlockType) -> bool {
+    is_water(block) && block.state() == 0
+}
+
+
+pub fn is_falling(block: BlockType) -> bool {
+    is_water(block) && block.state() == FALLING
+}
+
+
+// How far the water has flowed from a source. Falling water spreads like a source where it lands.
+pub fn flow_level(block: BlockType) -> u8 {
+    if is_falling(block) { 0 } else { block.state() }
+}
+
+
+// Height of the water surface in the block.
+pub fn water_height(block: BlockType) -> f32 {
+    if is_falling(block) {
+        return 1.0;
+    }
+    0.875 * (MAX_FLOW + 1 - flow_level(block)) as f32 / (MAX_FLOW + 1) as f32
+}
+
+
+// Blocks past the top of the world are air and past the bottom solid. None if the chunk isn't loaded.
+fn neighbour(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<BlockType> {
+    match position.1 {
+        y if y < 0 => Some(BlockType::BEDROCK),
+        y if y >= CHUNK_HEIGHT as i32 => Some(BlockType::AIR),
+        _ => block_at(chunks, position),
+    }
+}
+
+
+// Water spreads sideways from blocks it can't fall out of.
+fn spreads_sideways(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<bool> {
+    let below = neighbour(chunks, offset(position, DOWN))?;
+    Some(below != BlockType::AIR && (!is_water(below) || is_source(below)))
+}
+
+
+// What a block of air or water turns into on its next fluid tick, if it changes. Sources
+// never dry up. Nothing changes next to chunks that aren't loaded.
+pub fn next_water(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<BlockType> {
+    let block = neighbour(chunks, position)?;
+    if !(block == BlockType::AIR || is_water(block)) || is_source(block) {
+        return None;
+    }
+
+    let above = neighbour(chunks, offset(position, UP))?;
+    let below = neighbour(chunks, offset(position, DOWN))?;
+
+    let mut sources = 0;
+    let mut nearest = None;
+    for side in SIDES {
+        let side = offset(position, side);
+        let beside = neighbour(chunks, side)?;
+        if !is_water(beside) {
+            continue;
+        }
+
+        if is_source(beside) {
+            sources += 1;
+        }
+        if spreads_sideways(chunks, side)? {
+            let level = flow_level(beside);
+            nearest = Some(nearest.map_or(level, |nearest: u8| nearest.min(level)));
+        }
+    }
+
+    let next = if is_water(above) {
+        BlockType::WATER.with_state(FALLING)
+    }
+    // Water between two sources, on top of something it can't flow out of, turns into a source.
+    else if sources >= 2 && (!below.is_transparent() || is_source(below)) {
+        BlockType::WATER
+    }
+    else {
+        match nearest {
+            Some(level) if level < MAX_FLOW => BlockType::WATER.with_state(level + 1),
+            _ => BlockType::AIR,
+        }
+    };
+
+    (next != block).then_some(next)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHUNK_WIDTH;
+    use crate::plugins::world::testing::load_registries;
+    use crate::plugins::world::chunk::light::locate;
+
+
+    const FLOOR: i32 = 10;
+
+
+    // Chunks around (0, 0) of stone up to FLOOR and air above.
+    fn flat_world() -> HashMap<(i32, i32), ChunkData> {
+        let mut chunks = HashMap::new();
+
+        for chunk_pos in (-1..=1).flat_map(|x| (-1..=1).map(move |z| (x, z))) {
+            let mut blocks = ChunkData::new(BlockType::AIR);
+            for z in 0..CHUNK_WIDTH {
+                for y in 0..=FLOOR as usize {
+                    for x in 0..CHUNK_WIDTH {
+                        blocks.set(x, y, z, BlockType::STONE);
+                    }
+                }
+            }
+            chunks.insert(chunk_pos, blocks);
+        }
+
+        chunks
+    }
+
+
+    fn set(chunks: &mut HashMap<(i32, i32), ChunkData>, position: Position, block: BlockType) {
+        let (chunk, (x, y, z)) = locate(position).unwrap();
+        chunks.get_mut(&chunk).unwrap().set(x, y, z, block);
+    }
+
+
+    fn get(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> BlockType {
+        block_at(chunks, position).unwrap()
+    }
+
+
+    // Run fluid ticks over everything above the floor until the water stops moving.
+    fn settle(chunks: &mut HashMap<(i32, i32), ChunkData>) {
+        let width = CHUNK_WIDTH as i32;
+
+        for _ in 0..100 {
+            let changes: Vec<_> = (-width..2 * width)
+                .flat_map(|x| (FLOOR + 1..FLOOR + 8).flat_map(move |y| (-width..2 * width).map(move |z| (x, y, z))))
+                .filter_map(|position| next_water(chunks, position).map(|block| (position, block)))
+                .collect();
+
+            if changes.is_empty() {
+                return;
+            }
+            for (position, block) in changes {
+                set(chunks, position, block);
+            }
+        }
+
+        panic!("water didn't settle");
+    }
+
+
+    #[test]
+    fn water_spreads_from_a_source_and_dries_up_without_it() {
+        load_registries();
+        let mut chunks = flat_world();
+        let source = (4, FLOOR + 1, 4);
+        set(&mut chunks, source, BlockType::WATER);
+        settle(&mut chunks);
+
+        // Across the border into the next chunk, and no further than MAX_FLOW blocks.
+        for distance in 1..=MAX_FLOW as i32 {
+            assert_eq!(get(&chunks, (4 + distance, FLOOR + 1, 4)), BlockType::WATER.with_state(distance as u8));
+        }
+        assert_eq!(get(&chunks, (5 + MAX_FLOW as i32, FLOOR + 1, 4)), BlockType::AIR);
+        assert_eq!(get(&chunks, (5, FLOOR + 1, 5)), BlockType::WATER.with_state(2));
+
+        set(&mut chunks, source, BlockType::AIR);
+        settle(&mut chunks);
+        assert_eq!(get(&chunks, (5, FLOOR + 1, 4)), BlockType::AIR);
+        assert_eq!(get(&chunks, (4 + MAX_FLOW as i32, FLOOR + 1, 4)), BlockType::AIR);
+    }
+
+
+    #[test]
+    fn water_falls_off_edges_and_spreads_where_it_lands() {
+        load_registries();
+        let mut chunks = flat_world();
+        // A source on a pillar three blocks above the floor.
+        let pillar = (4, FLOOR + 3, 4);
+        for y in FLOOR + 1..=pillar.1 {
+            set(&mut chunks, (4, y, 4), BlockType::STONE);
+        }
+        set(&mut chunks, (4, pillar.1 + 1, 4), BlockType::WATER);
+        settle(&mut chunks);
+
+        assert_eq!(get(&chunks, (5, pillar.1 + 1, 4)), BlockType::WATER.with_state(1));
+        // It pours down rather than spreading further up on the pillar.
+        assert_eq!(get(&chunks, (6, pillar.1 + 1, 4)), BlockType::AIR);
+        for y in FLOOR + 1..=pillar.1 {
+            assert!(is_falling(get(&chunks, (5, y, 4))), "no falling water at height {}", y);
+        }
+        // Falling water spreads like a source where it lands.
+        assert_eq!(get(&chunks, (6, FLOOR + 1, 4)), BlockType::WATER.with_state(1));
+    }
+
+
+    #[test]
+    fn water_between_two_sources_becomes_one() {
+        load_registries();
+        let mut chunks = flat_world();
+        set(&mut chunks, (4, FLOOR + 1, 4), BlockType::WATER);
+        set(&mut chunks, (6, FLOOR + 1, 4), BlockType::WATER);
+        settle(&mut chunks);
+
+        assert!(is_source(get(&chunks, (5, FLOOR + 1, 4))));
+        // Next to only one source it stays flowing.
+        assert!(!is_source(get(&chunks, (3, FLOOR + 1, 4))));
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{CHUNK_WIDTH, CHUNK_HEIGHT, SECTION_HEIGHT, SECTION_COUNT};
+
+use super::{components::BlockType, data::ChunkData};
+
+
+pub const MAX_LIGHT: u8 = 15;
+
+// Packed light of a block open to the sky, with no block light.
+pub const FULL_SKY_LIGHT: u8 = MAX_LIGHT << 4;
+
+// A block position in world coordinates.
+pub type Position = (i32, i32, i32);
+
+const DIRECTIONS: [Position; 6] = [(1, 0, 0), (-1, 0, 0), (0, 0, 1), (0, 0, -1), (0, 1, 0), (0, -1, 0)];
+const DOWN: Position = (0, -1, 0);
+
+
+// Sky light comes in from the top of the world, block light from blocks with an emission.
+// Both are flood filled through transparent blocks, losing a level per block, except that
+// full sky light goes straight down without fading.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum LightChannel {
+    Sky,
+    Block,
+}
+
+
+impl LightChannel {
+    const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+
+    fn get(&self, light: u8) -> u8 {
+        match self {
+            LightChannel::Sky => light >> 4,
+            LightChannel::Block => light & 0x0F,
+        }
+    }
+
+    fn with(&self, light: u8, level: u8) -> u8 {
+        match self {
+            LightChannel::Sky => (light & 0x0F) | (level << 4),
+            LightChannel::Block => (light & 0xF0) | level,
+        }
+    }
+
+    fn spread(&self, level: u8, direction: Position) -> u8 {
+        if *self == LightChannel::Sky && direction == DOWN && level == MAX_LIGHT {
+            return MAX_LIGHT;
+        }
+        level.saturating_sub(1)
+    }
+}
+
+
+// Sky and block light levels out of a packed light value.
+pub fn light_levels(light: u8) -> (u8, u8) {
+    (LightChannel::Sky.get(light), LightChannel::Block.get(light))
+}
+
+
+pub fn world_position(chunk_pos: (i32, i32), x: usize, y: usize, z: usize) -> Position {
+    (chunk_pos.0 * CHUNK_WIDTH as i32 + x as i32, y as i32, chunk_pos.1 * CHUNK_WIDTH as i32 + z as i32)
+}
+
+
+// A chunk and a block position inside it.
+type ChunkCell = ((i32, i32), (usize, usize, usize));
+
+
+pub fn locate(position: Position) -> Option<ChunkCell> {
+    if position.1 < 0 || position.1 >= CHUNK_HEIGHT as i32 {
+        return None;
+    }
+
+    let width = CHUNK_WIDTH as i32;
+    Some((
+        (position.0.div_euclid(width), position.2.div_euclid(width)),
+        (position.0.rem_euclid(width) as usize, position.1 as usize, position.2.rem_euclid(width) as usize),
+    ))
+}
+
+
+pub fn offset(position: Position, direction: Position) -> Position {
+    (position.0 + direction.0, position.1 + direction.1, position.2 + direction.2)
+}
+
+
+pub fn block_at(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<BlockType> {
+    let (chunk, (x, y, z)) = locate(position)?;
+    chunks.get(&chunk).map(|blocks| blocks.get(x, y, z))
+}
+
+
+// The brighter of the sky and block light at a position.
+pub fn light_level_at(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<u8> {
+    let (chunk, (x, y, z)) = locate(position)?;
+    let (sky, block) = light_levels(chunks.get(&chunk)?.light(x, y, z));
+    Some(sky.max(block))
+}
+
+
+fn light_at(chunks: &HashMap<(i32, i32), ChunkData>, position: Position, channel: LightChannel) -> Option<u8> {
+    let (chunk, (x, y, z)) = locate(position)?;
+    chunks.get(&chunk).map(|blocks| channel.get(blocks.light(x, y, z)))
+}
+
+
+fn set_light_at(chunks: &mut HashMap<(i32, i32), ChunkData>, position: Position, channel: LightChannel, level: u8) {
+    let Some((chunk, (x, y, z))) = locate(position) else {
+        return;
+    };
+    if let Some(blocks) = chunks.get_mut(&chunk) {
+        let light = blocks.light(x, y, z);
+        blocks.set_light(x, y, z, channel.with(light, level));
+    }
+}
+
+
+// Spread light outwards from the queued positions. Chunks that aren't loaded stop the light,
+// it is spread into them when they are stitched in.
+fn propagate(
+    chunks: &mut HashMap<(i32, i32), ChunkData>,
+    channel: LightChannel,
+    mut queue: VecDeque<Position>,
+    changed: &mut Vec<Position>,
+) {
+    while let Some(position) = queue.pop_front() {
+        let Some(level) = light_at(chunks, position, channel) else {
+            continue;
+        };
+        if level <= 1 {
+            continue;
+        }
+
+        for direction in DIRECTIONS {
+            let neighbour = offset(position, direction);
+            if !block_at(chunks, neighbour).is_some_and(|block| block.is_transparent()) {
+                continue;
+            }
+
+            let spread = channel.spread(level, direction);
+            if light_at(chunks, neighbour, channel).is_some_and(|current| current < spread) {
+                set_light_at(chunks, neighbour, channel, spread);
+                changed.push(neighbour);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+}
+
+
+// Darken everything that got its light from the queued positions, which held the given
+// levels. Neighbours lit from elsewhere are added to `refill` to spread their light back in.
+fn unpropagate(
+    chunks: &mut HashMap<(i32, i32), ChunkData>,
+    channel: LightChannel,
+    mut queue: VecDeque<(Position, u8)>,
+    refill: &mut VecDeque<Position>,
+    changed: &mut Vec<Position>,
+) {
+    while let Some((position, level)) = queue.pop_front() {
+        for direction in DIRECTIONS {
+            let neighbour = offset(position, direction);
+            let Some(neighbour_level) = light_at(chunks, neighbour, channel) else {
+                continue;
+            };
+            if neighbour_level == 0 {
+                continue;
+            }
+
+            // Full sky light below full sky light came from above.
+            if neighbour_level < level || (channel == LightChannel::Sky && direction == DOWN && level == MAX_LIGHT) {
+                set_light_at(chunks, neighbour, channel, 0);
+                changed.push(neighbour);
+                queue.push_back((neighbour, neighbour_level));
+
+                let emission = block_at(chunks, neighbour).map(|block| block.emission()).unwrap_or_default();
+                if channel == LightChannel::Block && emission > 0 {
+                    set_light_at(chunks, neighbour, channel, emission);
+                    refill.push_back(neighbour);
+                }
+            }
+            else {
+                refill.push_back(neighbour);
+            }
+        }
+    }
+}
+
+
+// Light a freshly generated or loaded chunk on its own. Light from and into the neighbours
+// is added by stitch_chunk_light once the chunk is in the world.
+pub fn light_chunk(chunk_pos: (i32, i32), blocks: ChunkData) -> ChunkData {
+    let mut chunks = HashMap::from([(chunk_pos, blocks)]);
+    let blocks = chunks.get_mut(&chunk_pos).unwrap();
+
+    // Empty sections above the terrain are in full sky light.
+    let mut top = CHUNK_HEIGHT;
+    for section in (0..SECTION_COUNT).rev() {
+        if !blocks.section(section).is_empty() {
+            break;
+        }
+        blocks.section_mut(section).fill_light(FULL_SKY_LIGHT);
+        top = section * SECTION_HEIGHT;
+    }
+
+    // Sky light goes down every column until it hits something.
+    let mut lit = vec![];
+    for z in 0..CHUNK_WIDTH {
+        for x in 0..CHUNK_WIDTH {
+            for y in (0..top).rev() {
+                if !blocks.get(x, y, z).is_transparent() {
+                    break;
+                }
+                blocks.set_light(x, y, z, FULL_SKY_LIGHT);
+                lit.push(world_position(chunk_pos, x, y, z));
+            }
+        }
+    }
+
+    // Emitting blocks are only looked for in sections that have one in their palette.
+    let mut sources = VecDeque::new();
+    for section in 0..top / SECTION_HEIGHT {
+        if !blocks.section(section).palette().iter().any(|block| block.emission() > 0) {
+            continue;
+        }
+
+        for z in 0..CHUNK_WIDTH {
+            for y in section * SECTION_HEIGHT..(section + 1) * SECTION_HEIGHT {
+                for x in 0..CHUNK_WIDTH {
+                    let emission = blocks.get(x, y, z).emission();
+                    if emission > 0 {
+                        let light = blocks.light(x, y, z);
+                        blocks.set_light(x, y, z, LightChannel::Block.with(light, emission));
+                        sources.push_back(world_position(chunk_pos, x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    // Spread the sky light sideways, under overhangs and into caves open to the sky.
+    let mut changed = vec![];
+    propagate(&mut chunks, LightChannel::Sky, lit.into(), &mut changed);
+    propagate(&mut chunks, LightChannel::Block, sources, &mut changed);
+
+    chunks.remove(&chunk_pos).unwrap()
+}
+
+
+// Spread light across the borders between a chunk that was just added and its loaded
+// neighbours, both ways. Returns the positions whose light changed.
+pub fn stitch_chunk_light(chunks: &mut HashMap<(i32, i32), ChunkData>, chunk_pos: (i32, i32)) -> Vec<Position> {
+    let mut queue = VecDeque::new();
+    let last = CHUNK_WIDTH - 1;
+
+    for (dx, dz) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+        let neighbour_pos = (chunk_pos.0 + dx, chunk_pos.1 + dz);
+        let (Some(blocks), Some(neighbour)) = (chunks.get(&chunk_pos), chunks.get(&neighbour_pos)) else {
+            continue;
+        };
+
+        for section in 0..SECTION_COUNT {
+            // Nothing to spread between sections lit the same everywhere.
+            let uniform = blocks.section(section).uniform_light();
+            if uniform.is_some() && uniform == neighbour.section(section).uniform_light() {
+                continue;
+            }
+
+            for y in section * SECTION_HEIGHT..(section + 1) * SECTION_HEIGHT {
+                for i in 0..CHUNK_WIDTH {
+                    let (own, other) = match (dx, dz) {
+                        (1, _) => ((last, i), (0, i)),
+                        (-1, _) => ((0, i), (last, i)),
+                        (_, 1) => ((i, last), (i, 0)),
+                        _ => ((i, 0), (i, last)),
+                    };
+                    queue.push_back(world_position(chunk_pos, own.0, y, own.1));
+                    queue.push_back(world_position(neighbour_pos, other.0, y, other.1));
+                }
+            }
+        }
+    }
+
+    let mut changed = vec![];
+    for channel in LightChannel::ALL {
+        propagate(chunks, channel, queue.clone(), &mut changed);
+    }
+
+    changed
+}
+
+
+// Update the light around a block that was just changed. Returns the positions whose light changed.
+pub fn update_light(chunks: &mut HashMap<(i32, i32), ChunkData>, position: Position) -> Vec<Position> {
+    let mut changed = vec![];
+    let Some(block) = block_at(chunks, position) else {
+        return changed;
+    };
+
+    for channel in LightChannel::ALL {
+        let mut refill = VecDeque::new();
+
+        let old = light_at(chunks, position, channel).unwrap_or_default();
+        if old > 0 {
+            set_light_at(chunks, position, channel, 0);
+            changed.push(position);
+            unpropagate(chunks, channel, VecDeque::from([(position, old)]), &mut refill, &mut changed);
+        }
+
+        if channel == LightChannel::Block && block.emission() > 0 {
+            set_light_at(chunks, position, channel, block.emission());
+            changed.push(position);
+            refill.push_back(position);
+        }
+
+        // Let the light around flow back into a block that light passes through.
+        if block.is_transparent() {
+            if channel == LightChannel::Sky && position.1 == CHUNK_HEIGHT as i32 - 1 {
+                set_light_at(chunks, position, channel, MAX_LIGHT);
+                changed.push(position);
+            }
+            refill.push_back(position);
+            for direction in DIRECTIONS {
+                refill.push_back(offset(position, direction));
+            }
+        }
+
+        propagate(chunks, channel, refill, &mut changed);
+    }
+
+    changed
+}
+
+
+// Sections whose meshes show the light of the given positions: their own, and those of
+// the blocks next to them, whose faces are lit by them.
+pub fn lit_sections(changed: &[Position]) -> HashSet<((i32, i32), usize)> {
+    let mut sections = HashSet::new();
+
+    for position in changed {
+        for neighbour in DIRECTIONS.iter().map(|direction| offset(*position, *direction)).chain([*position]) {
+            if let Some((chunk, (_, y, _))) = locate(neighbour) {
+                sections.insert((chunk, y / SECTION_HEIGHT));
+            }
+        }
+    }
+
+    sections
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::world::testing::load_registries;
+
+
+    const GROUND: usize = 100;
+    const FLOOR: usize = 90;
+
+
+    // Two chunks side by side of stone up to GROUND, with a shaft open to the sky down to
+    // FLOOR in the first one, right against the border.
+    fn shaft_world() -> HashMap<(i32, i32), ChunkData> {
+        let mut chunks = HashMap::new();
+
+        for chunk_pos in [(0, 0), (1, 0)] {
+            let mut blocks = ChunkData::new(BlockType::AIR);
+            for z in 0..CHUNK_WIDTH {
+                for y in 0..=GROUND {
+                    for x in 0..CHUNK_WIDTH {
+                        blocks.set(x, y, z, BlockType::STONE);
+                    }
+                }
+            }
+            if chunk_pos == (0, 0) {
+                for y in FLOOR..=GROUND {
+                    blocks.set(CHUNK_WIDTH - 1, y, 3, BlockType::AIR);
+                }
+            }
+            chunks.insert(chunk_pos, light_chunk(chunk_pos, blocks));
+        }
+
+        stitch_chunk_light(&mut chunks, (1, 0));
+        chunks
+    }
+
+
+    fn sky(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> u8 {
+        light_at(chunks, position, LightChannel::Sky).unwrap()
+    }
+
+
+    fn set_block(chunks: &mut HashMap<(i32, i32), ChunkData>, position: Position, block: BlockType) {
+        let (chunk, (x, y, z)) = locate(position).unwrap();
+        chunks.get_mut(&chunk).unwrap().set(x, y, z, block);
+        update_light(chunks, position);
+    }
+
+
+    #[test]
+    fn edits_relight_across_chunk_borders() {
+        load_registries();
+        let mut chunks = shaft_world();
+        let (ground, floor) = (GROUND as i32, FLOOR as i32);
+        let bottom = (CHUNK_WIDTH as i32 - 1, floor, 3);
+        let tunnel = [(CHUNK_WIDTH as i32, floor, 3), (CHUNK_WIDTH as i32 + 1, floor, 3)];
+        assert_eq!(sky(&chunks, bottom), MAX_LIGHT);
+        assert_eq!(sky(&chunks, tunnel[0]), 0);
+
+        // Breaking into the next chunk lets the light in, a level less every block.
+        for position in tunnel {
+            set_block(&mut chunks, position, BlockType::AIR);
+        }
+        assert_eq!(sky(&chunks, tunnel[0]), MAX_LIGHT - 1);
+        assert_eq!(sky(&chunks, tunnel[1]), MAX_LIGHT - 2);
+
+        // Closing the shaft darkens it and the tunnel behind the border.
+        let lid = (CHUNK_WIDTH as i32 - 1, ground, 3);
+        set_block(&mut chunks, lid, BlockType::STONE);
+        for position in [(bottom.0, ground - 1, bottom.2), bottom, tunnel[0], tunnel[1]] {
+            assert_eq!(sky(&chunks, position), 0, "{:?} is still lit", position);
+        }
+
+        set_block(&mut chunks, lid, BlockType::AIR);
+        assert_eq!(sky(&chunks, bottom), MAX_LIGHT);
+        assert_eq!(sky(&chunks, tunnel[0]), MAX_LIGHT - 1);
+        assert_eq!(sky(&chunks, tunnel[1]), MAX_LIGHT - 2);
+    }
+}
@@ -0,0 +1,50 @@
+use bevy::{prelude::*, pbr::{MaterialPipeline, MaterialPipelineKey}, render::{mesh::{MeshVertexAttribute, MeshVertexBufferLayout}, render_resource::{AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, VertexFormat}}};
+
+
+// Atlas rectangle of the face's texture as (min u, min v, width, height). The mesh uvs
+// count tiles across the face, so a merged face repeats the texture once per block.
+pub const ATTRIBUTE_ATLAS_TILE: MeshVertexAttribute = MeshVertexAttribute::new("AtlasTile", 823_149_701, VertexFormat::Float32x4);
+// Colour the texture is multiplied by, such as the grass and leaf colours of biomes.
+pub const ATTRIBUTE_TINT: MeshVertexAttribute = MeshVertexAttribute::new("Tint", 823_149_702, VertexFormat::Float32x3);
+
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct ChunkMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub texture: Handle<Image>,
+    // How much of the sky light is let through, following the time of day.
+    #[uniform(2)]
+    pub daylight: f32,
+}
+
+
+impl Material for ChunkMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            ATTRIBUTE_ATLAS_TILE.at_shader_location(2),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(3),
+            ATTRIBUTE_TINT.at_shader_location(4),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::OnceLock;
+use serde::Deserialize;
+
+use crate::CHUNK_HEIGHT;
+
+use super::components::BlockType;
+
+
+pub const ORES_PATH: &str = "assets/ores.ron";
+
+static REGISTRY: OnceLock<Vec<OreProperties>> = OnceLock::new();
+
+
+pub struct OreProperties {
+    pub block: BlockType,
+    // Smallest and largest number of blocks in a vein.
+    pub vein_size: (usize, usize),
+    // Average number of veins started in a chunk, a fraction being the chance of one more.
+    pub veins_per_chunk: f32,
+    // Lowest and highest block a vein starts at.
+    pub height: (usize, usize),
+    pub distribution: HeightDistribution,
+    // Blocks the vein may take the place of, any other block stops it from growing there.
+    pub replaces: Vec<BlockType>,
+}
+
+
+// How the heights veins start at are spread between the lowest and the highest.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+pub enum HeightDistribution {
+    // Every height as likely.
+    Uniform,
+    // Most veins in the middle, fewer towards either end.
+    Triangle,
+    // Most veins at the bottom, fewer and fewer further up.
+    Deep,
+}
+
+
+#[derive(Deserialize)]
+struct OreDefinition {
+    name: String,
+    block: String,
+    vein_size: (usize, usize),
+    veins_per_chunk: f32,
+    height: (usize, usize),
+    #[serde(default = "default_distribution")]
+    distribution: HeightDistribution,
+    #[serde(default = "default_replaces")]
+    replaces: Vec<String>,
+}
+
+
+fn default_distribution() -> HeightDistribution {
+    HeightDistribution::Uniform
+}
+
+
+fn default_replaces() -> Vec<String> {
+    vec!["stone".to_string()]
+}
+
+
+pub fn ores() -> &'static [OreProperties] {
+    REGISTRY.get().expect("[E] Ore registry used before it was loaded!")
+}
+
+
+pub fn load_ore_registry(path: impl AsRef<Path>) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let registry = parse_ore_registry(&text)?;
+
+    REGISTRY.set(registry).map_err(|_| io::Error::new(ErrorKind::AlreadyExists, "ore registry is already loaded"))
+}
+
+
+fn parse_ore_registry(text: &str) -> io::Result<Vec<OreProperties>> {
+    let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+    let definitions: Vec<OreDefinition> = ron::from_str(text).map_err(|e| invalid(e.to_string()))?;
+
+    let mut ores = vec![];
+    let mut names = HashSet::new();
+
+    for definition in definitions {
+        if !names.insert(definition.name.clone()) {
+            return Err(invalid(format!("ore {} is defined twice", definition.name)));
+        }
+
+        let block = |name: &String| BlockType::from_name(name)
+            .ok_or_else(|| invalid(format!("ore {} uses missing block {}", definition.name, name)));
+
+        let (min_size, max_size) = definition.vein_size;
+        if min_size == 0 || min_size > max_size {
+            return Err(invalid(format!("ore {} has an invalid vein size", definition.name)));
+        }
+
+        // The bottom of the world is bedrock.
+        let (min_height, max_height) = definition.height;
+        if min_height == 0 || min_height > max_height || max_height >= CHUNK_HEIGHT {
+            return Err(invalid(format!("ore {} must start between 1 and {}", definition.name, CHUNK_HEIGHT - 1)));
+        }
+
+        // NaN would compare false against any bound, so it is refused along with the negatives.
+        if !(definition.veins_per_chunk.is_finite() && definition.veins_per_chunk >= 0.0) {
+            return Err(invalid(format!("ore {} must have a finite, non-negative number of veins", definition.name)));
+        }
+
+        ores.push(OreProperties {
+            block: block(&definition.block)?,
+            replaces: definition.replaces.iter().map(block).collect::<io::Result<_>>()?,
+            vein_size: definition.vein_size,
+            veins_per_chunk: definition.veins_per_chunk,
+            height: definition.height,
+            distribution: definition.distribution,
+        });
+    }
+
+    Ok(ores)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::world::testing::load_registries;
+
+
+    fn ore(veins_per_chunk: &str) -> String {
+        format!("[(name: \"coal\", block: \"ore_stone_coal\", vein_size: (2, 4), veins_per_chunk: {}, height: (5, 60))]", veins_per_chunk)
+    }
+
+
+    #[test]
+    fn ores_are_parsed_with_their_defaults() {
+        load_registries();
+        let ores = parse_ore_registry(&ore("1.5")).unwrap();
+
+        assert_eq!(ores.len(), 1);
+        assert_eq!(ores[0].block, BlockType::from_name("ore_stone_coal").unwrap());
+        assert_eq!(ores[0].veins_per_chunk, 1.5);
+        assert_eq!(ores[0].distribution, HeightDistribution::Uniform);
+        assert_eq!(ores[0].replaces, vec![BlockType::STONE]);
+    }
+
+
+    #[test]
+    fn vein_counts_that_are_not_a_number_are_refused() {
+        load_registries();
+
+        for veins_per_chunk in ["NaN", "-1.0", "inf"] {
+            let error = parse_ore_registry(&ore(veins_per_chunk)).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", veins_per_chunk);
+            assert!(error.to_string().contains("number of veins"), "{}: {}", veins_per_chunk, error);
+        }
+    }
+}
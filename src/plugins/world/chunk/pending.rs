@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::{CHUNK_WIDTH, CHUNK_HEIGHT};
+
+use super::{components::BlockType, data::ChunkData};
+
+
+// Blocks that generator features place in a chunk other than the one being generated,
+// by position inside the chunk they belong to.
+pub type PendingBlocks = HashMap<(usize, usize, usize), BlockType>;
+
+
+// Lets features write blocks at any offset from the chunk being generated. Blocks inside
+// the chunk are written straight away, the rest are kept as pending writes for their chunk.
+pub struct BlockWriter<'a> {
+    chunk_pos: (i32, i32),
+    blocks: &'a mut ChunkData,
+    pending: &'a mut HashMap<(i32, i32), PendingBlocks>,
+}
+
+
+impl<'a> BlockWriter<'a> {
+    pub fn new(chunk_pos: (i32, i32), blocks: &'a mut ChunkData, pending: &'a mut HashMap<(i32, i32), PendingBlocks>) -> Self {
+        BlockWriter { chunk_pos, blocks, pending }
+    }
+
+
+    // x and z are relative to the chunk being generated and may lie in any other chunk.
+    pub fn set(&mut self, x: i32, y: i32, z: i32, block: BlockType) {
+        if y < 0 || y >= CHUNK_HEIGHT as i32 {
+            return;
+        }
+
+        let width = CHUNK_WIDTH as i32;
+        let chunk = (self.chunk_pos.0 + x.div_euclid(width), self.chunk_pos.1 + z.div_euclid(width));
+        let position = (x.rem_euclid(width) as usize, y as usize, z.rem_euclid(width) as usize);
+
+        if chunk == self.chunk_pos {
+            self.blocks.set(position.0, position.1, position.2, block);
+        }
+        else {
+            self.pending.entry(chunk).or_default().insert(position, block);
+        }
+    }
+}
+
+
+// Where features of different chunks write the same cell, the solid block wins, then the one
+// with the higher id, so the world doesn't depend on which chunk was generated first.
+fn outranks(block: BlockType, other: BlockType) -> bool {
+    (block.is_solid(), block.raw()) > (other.is_solid(), other.raw())
+}
+
+
+// Add the writes of another chunk to the ones waiting for the same chunk.
+pub fn merge_pending(into: &mut PendingBlocks, from: PendingBlocks) {
+    for (position, block) in from {
+        let kept = into.entry(position).or_insert(block);
+        if outranks(block, *kept) {
+            *kept = block;
+        }
+    }
+}
+
+
+// Pending writes only fill air, so they never overwrite the terrain or what the player built.
+// The only blocks they replace are those of earlier pending writes they outrank, which are
+// tracked in applied. Returns the positions that changed.
+pub fn apply_pending_blocks(blocks: &mut ChunkData, pending: &PendingBlocks, applied: &mut PendingBlocks) -> Vec<(usize, usize, usize)> {
+    let mut changed = vec![];
+
+    for (&(x, y, z), &block) in pending.iter() {
+        let current = blocks.get(x, y, z);
+        let replaces = match applied.get(&(x, y, z)) {
+            Some(&earlier) => current == earlier && outranks(block, earlier),
+            None => current == BlockType::AIR,
+        };
+
+        if replaces {
+            blocks.set(x, y, z, block);
+            applied.insert((x, y, z), block);
+            changed.push((x, y, z));
+        }
+    }
+
+    changed
+}
@@ -0,0 +1,130 @@
+use crate::CHUNK_HEIGHT;
+
+use super::components::BlockType;
+
+
+// Layers of a superflat world unless others are given.
+pub const DEFAULT_LAYERS: &str = "bedrock, 3*stone, 3*dirt, grass";
+
+
+// How the terrain of a world is shaped. Picked when the world is created and saved with it,
+// since the same seed makes a different world with every preset.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum WorldPreset {
+    // Hills, mountains and seas shaped by the biomes.
+    #[default]
+    Default,
+    // The same terrain with its hills and mountains stretched far higher.
+    Amplified,
+    // Open sea with islands scattered over it.
+    Islands,
+    // Flat layers of blocks from the bottom up as (block, thickness), and nothing else.
+    Superflat(Vec<(BlockType, usize)>),
+}
+
+
+impl WorldPreset {
+    // Names of every preset, in the order they are offered when creating a world.
+    pub const NAMES: [&'static str; 4] = ["default", "amplified", "islands", "superflat"];
+
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorldPreset::Default => "default",
+            WorldPreset::Amplified => "amplified",
+            WorldPreset::Islands => "islands",
+            WorldPreset::Superflat(_) => "superflat",
+        }
+    }
+
+
+    // Preset of a name. The layers, written as in DEFAULT_LAYERS, are only read for superflat worlds.
+    pub fn parse(name: &str, layers: &str) -> Result<WorldPreset, String> {
+        match name.trim() {
+            "default" => Ok(WorldPreset::Default),
+            "amplified" => Ok(WorldPreset::Amplified),
+            "islands" => Ok(WorldPreset::Islands),
+            "superflat" => parse_layers(layers).map(WorldPreset::Superflat),
+            name => Err(format!("there is no world type {}", name)),
+        }
+    }
+
+
+    // Layers of a superflat world, written the way parse reads them.
+    pub fn layers(&self) -> Option<String> {
+        let WorldPreset::Superflat(layers) = self else {
+            return None;
+        };
+
+        let layers: Vec<String> = layers.iter().map(|(block, thickness)| match thickness {
+            1 => block.properties().name.clone(),
+            _ => format!("{}*{}", thickness, block.properties().name),
+        }).collect();
+
+        Some(layers.join(", "))
+    }
+}
+
+
+// Layers are separated by commas, each a block name with its thickness in front if more
+// than one: "bedrock, 3*stone, grass".
+fn parse_layers(text: &str) -> Result<Vec<(BlockType, usize)>, String> {
+    if text.trim().is_empty() {
+        return Err("a superflat world needs at least one layer".to_string());
+    }
+
+    let too_high = || format!("the layers can be at most {} blocks high", CHUNK_HEIGHT - 1);
+    let mut layers = vec![];
+    let mut height: usize = 0;
+
+    for layer in text.split(',').map(str::trim) {
+        let (thickness, name) = match layer.split_once('*') {
+            Some((thickness, name)) => {
+                let thickness: usize = thickness.trim().parse().ok().filter(|thickness| *thickness > 0)
+                    .ok_or_else(|| format!("{} is not a thickness", thickness.trim()))?;
+                (thickness, name.trim())
+            }
+            None => (1, layer),
+        };
+
+        height = height.checked_add(thickness).filter(|height| *height < CHUNK_HEIGHT).ok_or_else(too_high)?;
+
+        let block = BlockType::from_name(name).ok_or_else(|| format!("there is no block {}", name))?;
+        layers.push((block, thickness));
+    }
+
+    Ok(layers)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::world::testing::load_registries;
+
+
+    #[test]
+    fn layers_are_parsed_and_written_back() {
+        load_registries();
+        let preset = WorldPreset::parse("superflat", " bedrock,3*stone , grass").unwrap();
+        assert_eq!(preset, WorldPreset::Superflat(vec![(BlockType::BEDROCK, 1), (BlockType::STONE, 3), (BlockType::GRASS, 1)]));
+        assert_eq!(preset.layers().as_deref(), Some("bedrock, 3*stone, grass"));
+
+        assert!(WorldPreset::parse("superflat", "").is_err());
+        assert!(WorldPreset::parse("superflat", "0*stone").is_err());
+        assert!(WorldPreset::parse("superflat", "2*nothing").is_err());
+    }
+
+
+    #[test]
+    fn layers_higher_than_the_world_are_refused() {
+        load_registries();
+        let too_high = Err(format!("the layers can be at most {} blocks high", CHUNK_HEIGHT - 1));
+
+        assert!(WorldPreset::parse("superflat", &format!("{}*stone", CHUNK_HEIGHT - 1)).is_ok());
+        assert_eq!(WorldPreset::parse("superflat", &format!("{}*stone", CHUNK_HEIGHT)), too_high);
+        assert_eq!(WorldPreset::parse("superflat", &format!("{}*stone, stone", CHUNK_HEIGHT - 1)), too_high);
+        // Thicknesses that add up past the largest number don't wrap around to a small height.
+        assert_eq!(WorldPreset::parse("superflat", &format!("{}*stone, 1*stone", usize::MAX)), too_high);
+    }
+}
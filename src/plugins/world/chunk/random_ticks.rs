@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use rand::Rng;
+
+use super::{components::BlockType, data::ChunkData, fluid::is_water, light::{Position, block_at, light_level_at, offset}};
+
+
+// Light the block above dirt needs for grass to spread onto it.
+const GRASS_SPREAD_LIGHT: u8 = 9;
+// Leaves further than this from any wood log decay.
+const LEAF_DECAY_RANGE: i32 = 4;
+// Cacti don't grow taller than this, and grow one block in CACTUS_GROWTH_CHANCE ticks.
+const MAX_CACTUS_HEIGHT: i32 = 3;
+const CACTUS_GROWTH_CHANCE: u32 = 8;
+
+const UP: Position = (0, 1, 0);
+
+
+// Blocks that do something when picked by a random tick, so sections without any are skipped.
+pub fn ticks_randomly(block: BlockType) -> bool {
+    [BlockType::DIRT, BlockType::GRASS, BlockType::LEAVES, BlockType::CACTUS].contains(&block)
+}
+
+
+// What happens to a block picked by a random tick. Returns the blocks that change.
+pub fn random_tick(chunks: &HashMap<(i32, i32), ChunkData>, position: Position, random: &mut impl Rng) -> Vec<(Position, BlockType)> {
+    let Some(block) = block_at(chunks, position) else {
+        return vec![];
+    };
+
+    let changed = match block {
+        BlockType::DIRT => grass_spread(chunks, position),
+        BlockType::GRASS => grass_death(chunks, position),
+        BlockType::LEAVES => leaf_decay(chunks, position),
+        BlockType::CACTUS => return cactus_growth(chunks, position, random),
+        _ => None,
+    };
+
+    changed.map(|block| vec![(position, block)]).unwrap_or_default()
+}
+
+
+// Dirt in the light next to grass, from a block below to three above it, turns into grass.
+fn grass_spread(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<BlockType> {
+    let above = offset(position, UP);
+    if !is_open(chunks, above) || light_level_at(chunks, above)? < GRASS_SPREAD_LIGHT {
+        return None;
+    }
+
+    let near_grass = (-1..=1).any(|dx| (-1..=3).any(|dy| (-1..=1).any(|dz| {
+        block_at(chunks, offset(position, (dx, dy, dz))) == Some(BlockType::GRASS)
+    })));
+
+    near_grass.then_some(BlockType::GRASS)
+}
+
+
+// Grass covered by something it can't grow through turns back into dirt.
+fn grass_death(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<BlockType> {
+    let above = offset(position, UP);
+    (block_at(chunks, above).is_some() && !is_open(chunks, above)).then_some(BlockType::DIRT)
+}
+
+
+// Leaves that have lost their tree fall apart.
+fn leaf_decay(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> Option<BlockType> {
+    let range = -LEAF_DECAY_RANGE..=LEAF_DECAY_RANGE;
+
+    let mut unloaded = false;
+    for dx in range.clone() {
+        for dy in range.clone() {
+            for dz in range.clone() {
+                match block_at(chunks, offset(position, (dx, dy, dz))) {
+                    Some(BlockType::WOOD_LOG) => return None,
+                    // Don't decay next to chunks that aren't loaded, the log might be there.
+                    None => unloaded = true,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    (!unloaded).then_some(BlockType::AIR)
+}
+
+
+// Cacti grow upwards into air, up to MAX_CACTUS_HEIGHT blocks.
+fn cactus_growth(chunks: &HashMap<(i32, i32), ChunkData>, position: Position, random: &mut impl Rng) -> Vec<(Position, BlockType)> {
+    let mut top = position;
+    while block_at(chunks, offset(top, UP)) == Some(BlockType::CACTUS) {
+        top = offset(top, UP);
+    }
+
+    let mut bottom = position;
+    while block_at(chunks, offset(bottom, (0, -1, 0))) == Some(BlockType::CACTUS) {
+        bottom = offset(bottom, (0, -1, 0));
+    }
+
+    let above = offset(top, UP);
+    if top.1 - bottom.1 + 1 >= MAX_CACTUS_HEIGHT || block_at(chunks, above) != Some(BlockType::AIR) || random.gen_range(0..CACTUS_GROWTH_CHANCE) != 0 {
+        return vec![];
+    }
+
+    vec![(above, BlockType::CACTUS)]
+}
+
+
+// Air above a block, or another block that light and plants pass through, but not water.
+fn is_open(chunks: &HashMap<(i32, i32), ChunkData>, position: Position) -> bool {
+    block_at(chunks, position).is_some_and(|block| block.is_transparent() && !is_water(block))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHUNK_WIDTH;
+    use crate::plugins::world::chunk::light::{light_chunk, locate};
+    use crate::plugins::world::testing::load_registries;
+
+
+    const FLOOR: i32 = 60;
+
+
+    // Chunks around (0, 0) of stone with a layer of dirt at FLOOR and air above, with the
+    // given blocks placed before they are lit.
+    fn world(blocks: &[(Position, BlockType)]) -> HashMap<(i32, i32), ChunkData> {
+        let mut chunks = HashMap::new();
+
+        for chunk_pos in (-1..=1).flat_map(|x| (-1..=1).map(move |z| (x, z))) {
+            let mut data = ChunkData::new(BlockType::AIR);
+            for z in 0..CHUNK_WIDTH {
+                for y in 0..=FLOOR as usize {
+                    for x in 0..CHUNK_WIDTH {
+                        data.set(x, y, z, if y == FLOOR as usize { BlockType::DIRT } else { BlockType::STONE });
+                    }
+                }
+            }
+            chunks.insert(chunk_pos, data);
+        }
+
+        for &(position, block) in blocks {
+            let (chunk, (x, y, z)) = locate(position).unwrap();
+            chunks.get_mut(&chunk).unwrap().set(x, y, z, block);
+        }
+
+        chunks.into_iter().map(|(chunk_pos, data)| (chunk_pos, light_chunk(chunk_pos, data))).collect()
+    }
+
+
+    #[test]
+    fn grass_spreads_onto_lit_dirt_near_it() {
+        load_registries();
+        let dirt = (4, FLOOR, 4);
+
+        let chunks = world(&[((5, FLOOR, 4), BlockType::GRASS)]);
+        assert_eq!(grass_spread(&chunks, dirt), Some(BlockType::GRASS));
+        assert_eq!(grass_spread(&chunks, (2, FLOOR, 4)), None);
+
+        // From a ledge up to three blocks above, but not from more than one below.
+        let ledge = [((5, FLOOR + 1, 4), BlockType::STONE), ((5, FLOOR + 2, 4), BlockType::GRASS)];
+        assert_eq!(grass_spread(&world(&ledge), dirt), Some(BlockType::GRASS));
+        let below = [((5, FLOOR - 2, 4), BlockType::GRASS)];
+        assert_eq!(grass_spread(&world(&below), dirt), None);
+
+        // Not under a block, or under water.
+        for cover in [BlockType::STONE, BlockType::WATER] {
+            let covered = [((5, FLOOR, 4), BlockType::GRASS), ((4, FLOOR + 1, 4), cover)];
+            assert_eq!(grass_spread(&world(&covered), dirt), None, "grass spread under {:?}", cover);
+        }
+    }
+
+
+    #[test]
+    fn covered_grass_dies() {
+        load_registries();
+        let grass = (4, FLOOR, 4);
+
+        assert_eq!(grass_death(&world(&[(grass, BlockType::GRASS)]), grass), None);
+        for cover in [BlockType::STONE, BlockType::WATER] {
+            let chunks = world(&[(grass, BlockType::GRASS), ((4, FLOOR + 1, 4), cover)]);
+            assert_eq!(grass_death(&chunks, grass), Some(BlockType::DIRT), "grass lived under {:?}", cover);
+        }
+    }
+
+
+    #[test]
+    fn leaves_decay_away_from_logs() {
+        load_registries();
+        let leaves = (4, FLOOR + 5, 4);
+
+        let tree = [((4, FLOOR + 1, 4), BlockType::WOOD_LOG), (leaves, BlockType::LEAVES)];
+        assert_eq!(leaf_decay(&world(&tree), leaves), None);
+        assert_eq!(leaf_decay(&world(&[(leaves, BlockType::LEAVES)]), leaves), Some(BlockType::AIR));
+
+        // Leaves within reach of a chunk that isn't loaded wait for it.
+        let edge = (1 - CHUNK_WIDTH as i32, FLOOR + 5, 4);
+        assert_eq!(leaf_decay(&world(&[(edge, BlockType::LEAVES)]), edge), None);
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::OnceLock;
+use serde::Deserialize;
+
+use super::components::{BlockType, Face, MAX_BLOCK_ID};
+use super::light::MAX_LIGHT;
+
+
+pub const BLOCKS_PATH: &str = "assets/blocks.ron";
+
+// Set once at startup. Meshing and generation run on the task pool, so the registry
+// is kept out of the ECS and reached through BlockType instead.
+static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+
+pub struct BlockProperties {
+    pub name: String,
+    // Atlas rectangle of every face as (min u, min v, width, height), in Face order.
+    pub tiles: [[f32; 4]; 6],
+    // Faces of blocks next to a transparent block are drawn.
+    pub transparent: bool,
+    // Solid blocks collide with the player and can't be placed into.
+    pub solid: bool,
+    pub breakable: bool,
+    // Block light the block gives off, up to MAX_LIGHT.
+    pub emission: u8,
+    // Blocks that fall when there is nothing solid under them.
+    pub falls: bool,
+}
+
+
+pub struct BlockRegistry {
+    blocks: Vec<Option<BlockProperties>>,
+}
+
+
+impl BlockRegistry {
+    pub fn get(&self, id: u16) -> Option<&BlockProperties> {
+        self.blocks.get(id as usize).and_then(Option::as_ref)
+    }
+
+    pub fn find(&self, name: &str) -> Option<u16> {
+        self.blocks.iter().position(|block| block.as_ref().is_some_and(|block| block.name == name)).map(|id| id as u16)
+    }
+}
+
+
+#[derive(Deserialize)]
+struct BlockDefinition {
+    id: u16,
+    name: String,
+    #[serde(default)]
+    textures: FaceTextures,
+    #[serde(default)]
+    transparent: bool,
+    #[serde(default = "default_true")]
+    solid: bool,
+    #[serde(default = "default_true")]
+    breakable: bool,
+    #[serde(default)]
+    emission: u8,
+    #[serde(default)]
+    falls: bool,
+}
+
+
+#[derive(Deserialize, Default)]
+struct FaceTextures {
+    all: Option<String>,
+    side: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+    front: Option<String>,
+    back: Option<String>,
+}
+
+
+impl FaceTextures {
+    // The most specific texture given for the face wins.
+    fn texture(&self, face: Face) -> Option<&String> {
+        let (single, side) = match face {
+            Face::Right => (&self.right, &self.side),
+            Face::Left => (&self.left, &self.side),
+            Face::Back => (&self.back, &self.side),
+            Face::Front => (&self.front, &self.side),
+            Face::Bottom => (&self.bottom, &None),
+            Face::Top => (&self.top, &None),
+        };
+
+        single.as_ref().or(side.as_ref()).or(self.all.as_ref())
+    }
+}
+
+
+fn default_true() -> bool {
+    true
+}
+
+
+pub fn registry() -> &'static BlockRegistry {
+    REGISTRY.get().expect("[E] Block registry used before it was loaded!")
+}
+
+
+// Textures are looked up by name in the atlas tiles.
+pub fn load_block_registry(path: impl AsRef<Path>, atlas_tiles: &HashMap<String, [f32; 4]>) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let registry = parse_block_registry(&text, atlas_tiles)?;
+
+    REGISTRY.set(registry).map_err(|_| io::Error::new(ErrorKind::AlreadyExists, "block registry is already loaded"))
+}
+
+
+fn parse_block_registry(text: &str, atlas_tiles: &HashMap<String, [f32; 4]>) -> io::Result<BlockRegistry> {
+    let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+    let definitions: Vec<BlockDefinition> = ron::from_str(text).map_err(|e| invalid(e.to_string()))?;
+
+    let mut registry = BlockRegistry { blocks: vec![] };
+    let mut names = HashSet::new();
+
+    for definition in definitions {
+        if definition.id > MAX_BLOCK_ID {
+            return Err(invalid(format!("id of block {} is above {}", definition.name, MAX_BLOCK_ID)));
+        }
+
+        let id = definition.id as usize;
+        if registry.blocks.len() <= id {
+            registry.blocks.resize_with(id + 1, || None);
+        }
+        if registry.blocks[id].is_some() {
+            return Err(invalid(format!("block id {} is defined twice", id)));
+        }
+        if !names.insert(definition.name.clone()) {
+            return Err(invalid(format!("block {} is defined twice", definition.name)));
+        }
+
+        if definition.emission > MAX_LIGHT {
+            return Err(invalid(format!("emission of block {} is above {}", definition.name, MAX_LIGHT)));
+        }
+
+        let mut tiles = [[0.0; 4]; 6];
+        for face in Face::ALL {
+            match definition.textures.texture(face) {
+                Some(texture) => {
+                    tiles[face as usize] = *atlas_tiles.get(texture)
+                        .ok_or_else(|| invalid(format!("block {} uses missing texture {}", definition.name, texture)))?;
+                }
+                // Only blocks that are never drawn may leave faces without a texture.
+                None if definition.id != BlockType::AIR.id() => {
+                    return Err(invalid(format!("block {} has no texture for its {:?} face", definition.name, face)));
+                }
+                None => {}
+            }
+        }
+
+        registry.blocks[id] = Some(BlockProperties {
+            name: definition.name,
+            tiles,
+            transparent: definition.transparent,
+            solid: definition.solid,
+            breakable: definition.breakable,
+            emission: definition.emission,
+            falls: definition.falls,
+        });
+    }
+
+    // World generation and the game refer to these blocks directly.
+    for (block, name) in BlockType::BUILTIN {
+        match registry.get(block.id()) {
+            Some(properties) if properties.name == name => {}
+            _ => return Err(invalid(format!("block {} must be defined with id {}", name, block.id()))),
+        }
+    }
+
+    Ok(registry)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    const TILE: [f32; 4] = [0.0, 0.5, 0.25, 0.5];
+
+
+    fn tiles() -> HashMap<String, [f32; 4]> {
+        HashMap::from([("tile".to_string(), TILE), ("top".to_string(), [0.5; 4])])
+    }
+
+
+    // The blocks every registry must have, followed by the given definitions.
+    fn definitions(extra: &str) -> String {
+        let builtin: String = BlockType::BUILTIN.iter()
+            .map(|(block, name)| match *block == BlockType::AIR {
+                true => format!("(id: {}, name: \"{}\", transparent: true, solid: false),", block.id(), name),
+                false => format!("(id: {}, name: \"{}\", textures: (all: \"tile\")),", block.id(), name),
+            })
+            .collect();
+
+        format!("#![enable(implicit_some)] [{}{}]", builtin, extra)
+    }
+
+
+    fn parse_error(extra: &str) -> String {
+        match parse_block_registry(&definitions(extra), &tiles()) {
+            Ok(_) => panic!("{} was accepted", extra),
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::InvalidData);
+                e.to_string()
+            }
+        }
+    }
+
+
+    #[test]
+    fn blocks_are_parsed_with_their_defaults() {
+        let registry = parse_block_registry(&definitions("(id: 40, name: \"lamp\", textures: (all: \"tile\", top: \"top\"), emission: 12, falls: true)"), &tiles()).unwrap();
+        let id = registry.find("lamp").unwrap();
+        let lamp = registry.get(id).unwrap();
+
+        assert_eq!(id, 40);
+        assert_eq!(lamp.tiles[Face::Top as usize], [0.5; 4]);
+        assert_eq!(lamp.tiles[Face::Left as usize], TILE);
+        assert_eq!((lamp.emission, lamp.falls), (12, true));
+        assert!(lamp.solid && lamp.breakable && !lamp.transparent);
+
+        assert!(registry.get(39).is_none());
+        assert!(!registry.get(BlockType::AIR.id()).unwrap().solid);
+    }
+
+
+    #[test]
+    fn bad_definitions_are_refused() {
+        assert!(parse_error("(id: 40, name: \"stone\", textures: (all: \"tile\"))").contains("block stone is defined twice"));
+        assert!(parse_error("(id: 3, name: \"slab\", textures: (all: \"tile\"))").contains("block id 3 is defined twice"));
+        assert!(parse_error("(id: 40, name: \"lamp\", textures: (all: \"glow\"))").contains("missing texture glow"));
+        assert!(parse_error("(id: 40, name: \"lamp\", textures: (side: \"tile\"))").contains("no texture for its"));
+        assert!(parse_error("(id: 40, name: \"lamp\", textures: (all: \"tile\"), emission: 16)").contains("emission of block lamp"));
+        assert!(parse_error("(id: 40, textures: (all: \"tile\"))").contains("name"));
+    }
+
+
+    #[test]
+    fn builtin_blocks_must_be_defined() {
+        let text = definitions("").replace("name: \"cactus\"", "name: \"cacti\"");
+        let error = parse_block_registry(&text, &tiles()).err().unwrap();
+
+        assert!(error.to_string().contains("block cactus must be defined"));
+    }
+}
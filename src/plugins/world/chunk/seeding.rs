@@ -0,0 +1,107 @@
+use std::time::SystemTime;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+
+// Random numbers of world generation. ChaCha8 gives the same numbers on every platform and
+// version of rand, so a seed keeps making the same world.
+pub type WorldRng = ChaCha8Rng;
+
+
+// What a stream of random numbers is for. Every feature draws from streams of its own, so
+// drawing more numbers in one doesn't shift those of another.
+#[derive(Clone, Copy, Debug)]
+pub enum Stream {
+    Ores = 1,
+    Plants = 2,
+    Vegetation = 3,
+    Tunnels = 4,
+    SurfaceMix = 5,
+    TerrainNoise = 6,
+    TreeNoise = 7,
+    TemperatureNoise = 8,
+    MoistureNoise = 9,
+    CaveNoise = 10,
+    TunnelNoise = 11,
+}
+
+
+// The splitmix64 finaliser: every bit of the input affects every bit of the output, so
+// neighbouring positions get unrelated hashes.
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+
+// Hash of the world seed, a stream and a position. Mixing in one value at a time keeps
+// (x, z) and (z, x) apart.
+pub fn position_hash(seed: u64, stream: Stream, x: i32, z: i32) -> u64 {
+    let hash = mix(seed.wrapping_add(stream as u64));
+    let hash = mix(hash.wrapping_add(x as i64 as u64));
+    mix(hash.wrapping_add(z as i64 as u64))
+}
+
+
+// Random numbers of a stream for one chunk. They depend only on the seed and the chunk, never
+// on which chunks were generated before it.
+pub fn chunk_rng(seed: u64, stream: Stream, chunk_pos: (i32, i32)) -> WorldRng {
+    WorldRng::seed_from_u64(position_hash(seed, stream, chunk_pos.0, chunk_pos.1))
+}
+
+
+// Value from 0 to 1 of a stream for one column of the world.
+pub fn column_value(seed: u64, stream: Stream, x: i32, z: i32) -> f32 {
+    (position_hash(seed, stream, x, z) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+
+// Seed of a noise function, each drawn from its own stream so no two share one.
+pub fn noise_seed(seed: u64, stream: Stream) -> u32 {
+    (position_hash(seed, stream, 0, 0) >> 32) as u32
+}
+
+
+// Seeds are typed as a number, or as any other text, which is hashed into one. Without any
+// text the seed is picked at random.
+pub fn parse_seed(text: &str) -> u64 {
+    let text = text.trim();
+    if text.is_empty() {
+        return random_seed();
+    }
+
+    text.parse::<u64>()
+        .or_else(|_| text.parse::<i64>().map(|seed| seed as u64))
+        .unwrap_or_else(|_| text_hash(text))
+}
+
+
+pub fn random_seed() -> u64 {
+    let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("[E] SystemTime before UNIX EPOCH!");
+    mix(time.as_nanos() as u64)
+}
+
+
+// FNV-1a, so the same text gives the same seed in every build.
+fn text_hash(text: &str) -> u64 {
+    text.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    // Seeds are shared as text, so the same text has to keep giving the same world.
+    #[test]
+    fn typed_seeds_are_stable() {
+        assert_eq!(parse_seed("12345"), 12345);
+        assert_eq!(parse_seed(" 12345 "), 12345);
+        assert_eq!(parse_seed("-1"), u64::MAX);
+        assert_eq!(parse_seed("budgetcraft"), parse_seed("budgetcraft"));
+        assert_ne!(parse_seed("budgetcraft"), parse_seed("BudgetCraft"));
+        assert_eq!(parse_seed("budgetcraft"), 0xF6D4_6E5A_8451_0EDA);
+    }
+}
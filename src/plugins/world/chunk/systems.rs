@@ -2,440 +2,589 @@ use std::collections::HashMap;
 use bevy::{prelude::*, render::{render_resource::PrimitiveTopology, mesh}};
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy_rapier3d::prelude::{Collider, ComputedColliderShape, Friction, CoefficientCombineRule};
-use noise::{Perlin, NoiseFn};
-use rand::{rngs::StdRng, SeedableRng, Rng};
+use noise::NoiseFn;
+use rand::Rng;
 
-use crate::{CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_VOL, plugins::world::{WorldMap, SeededPerlin}};
+use crate::{CHUNK_WIDTH, CHUNK_HEIGHT, SECTION_HEIGHT, SECTION_COUNT, plugins::world::{WorldMap, SeededPerlin, ChunkMaterials}};
 
 use self::structures_generation::{add_tree, add_cactus};
-
-use super::components::BlockType;
+use self::cave_generation::carve_caves;
+use self::ore_generation::generate_ores;
+use self::greedy_meshing::greedy_faces;
+
+use super::components::{BlockType, Face};
+use super::data::ChunkData;
+use super::pending::{BlockWriter, PendingBlocks};
+use super::material::{ATTRIBUTE_ATLAS_TILE, ATTRIBUTE_TINT};
+use super::light::{FULL_SKY_LIGHT, MAX_LIGHT, light_levels};
+use super::fluid::{is_water, water_height};
+use super::biome::{Vegetation, Feature, ChunkTints, blend_at};
+use super::seeding::{WorldRng, Stream, chunk_rng, column_value};
+use super::preset::WorldPreset;
 
 mod structures_generation;
+mod greedy_meshing;
+mod cave_generation;
+mod ore_generation;
+#[cfg(test)]
+mod tests;
 
 
 const SEA_LEVEL: usize = 62;
+// Highest the ground may reach, leaving room above it for trees.
+const MAX_GROUND: f32 = (CHUNK_HEIGHT - 32) as f32;
+// Amplified worlds stretch the terrain above the middle height this many times.
+const AMPLIFICATION: f32 = 4.0;
+// Scale of the noise islands are shaped by, and how far above and below the sea it raises and sinks the ground.
+const ISLAND_SCALE: f64 = 0.006;
+const ISLAND_DEPTH: f32 = 60.0;
+
+
+// A chunk produced off the main thread. Blocks that features placed outside the
+// chunk are handed back separately, keyed by the chunk they belong to.
+pub struct GeneratedChunk {
+    pub position: (i32, i32),
+    pub blocks: ChunkData,
+    pub pending: HashMap<(i32, i32), PendingBlocks>,
+    // Read from disk rather than generated.
+    pub loaded: bool,
+    // Blocks with nothing under them that should fall.
+    pub unsupported: Vec<(usize, usize, usize)>,
+}
 
 
-pub fn generate_chunk_data(perlin: &Res<SeededPerlin>, chunk_pos: (i32, i32), world_map: &mut ResMut<WorldMap>) {
+pub fn generate_chunk_data(perlin: &SeededPerlin, chunk_pos: (i32, i32)) -> GeneratedChunk {
 
-    let seed = (perlin.seed).wrapping_add(chunk_pos.0 as u32).wrapping_add(chunk_pos.1 as u32);
-    let mut random = StdRng::seed_from_u64(seed as u64);
+    let mut blocks = ChunkData::new(BlockType::AIR);
+    let mut pending = HashMap::new();
 
-    let mut blocks = [BlockType::Air; CHUNK_VOL];
+    if let WorldPreset::Superflat(layers) = &perlin.preset {
+        generate_layers(layers, &mut blocks);
+        blocks.compact();
+
+        return GeneratedChunk { position: chunk_pos, blocks, pending, loaded: false, unsupported: vec![] };
+    }
 
     generate_terrain_shape(perlin, chunk_pos, &mut blocks);
-    generate_ore(&mut random, &mut blocks);
-    generate_terrain_cover(perlin, chunk_pos, &mut blocks, world_map);
+    generate_ores(&mut chunk_rng(perlin.seed, Stream::Ores, chunk_pos), &mut blocks);
+    let plants = generate_terrain_cover(perlin, chunk_pos, &mut blocks);
+    carve_caves(perlin, chunk_pos, &mut blocks);
+    generate_plants(&mut chunk_rng(perlin.seed, Stream::Plants, chunk_pos), chunk_pos, &plants, &mut blocks, &mut pending);
+    blocks.compact();
 
-    world_map.chunks.insert(chunk_pos, blocks);
+    GeneratedChunk { position: chunk_pos, blocks, pending, loaded: false, unsupported: vec![] }
 }
 
 
-pub fn generate_terrain_shape(perlin: &Res<SeededPerlin>, chunk_pos: (i32, i32), blocks: &mut [BlockType; CHUNK_VOL]) {
+pub fn generate_terrain_shape(perlin: &SeededPerlin, chunk_pos: (i32, i32), blocks: &mut ChunkData) {
 
-    for i in 0 .. CHUNK_VOL {
+    // Only walk each column up to its surface, the air above is already there.
+    for z in 0 .. CHUNK_WIDTH {
+        for x in 0 .. CHUNK_WIDTH {
 
-        let z = i / (CHUNK_WIDTH * CHUNK_HEIGHT);
-        let y = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) / CHUNK_WIDTH;
-        let x = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) % CHUNK_WIDTH;
+            let (height, _) = height_by_coords(perlin, x, z, chunk_pos);
 
-        let (height, _) = height_by_coords(perlin.terrain_noise, x, z, chunk_pos);
+            blocks.set(x, 0, z, BlockType::BEDROCK);
 
-        let index = x + y * CHUNK_WIDTH + z * CHUNK_WIDTH * CHUNK_HEIGHT;
-
-        if y == 0 {
-            blocks[index] = BlockType::BedRock;
-        }
-        else if y < height {
-            blocks[index] = BlockType::Stone;
+            for y in 1 .. height.min(CHUNK_HEIGHT) {
+                blocks.set(x, y, z, BlockType::STONE);
+            }
         }
     }
 }
 
 
-pub fn generate_terrain_cover(perlin: &Res<SeededPerlin>, chunk_pos: (i32, i32), blocks: &mut [BlockType; CHUNK_VOL], world_map: &mut ResMut<WorldMap>) {
-
-    let mut tree_positions = vec![];
-    let mut random = StdRng::seed_from_u64(perlin.seed as u64);
+fn generate_layers(layers: &[(BlockType, usize)], blocks: &mut ChunkData) {
+    let layers = layers.iter().flat_map(|(block, thickness)| std::iter::repeat_n(*block, *thickness));
 
-    for i in 0 .. CHUNK_VOL {
+    for (y, block) in layers.enumerate() {
+        for z in 0 .. CHUNK_WIDTH {
+            for x in 0 .. CHUNK_WIDTH {
+                blocks.set(x, y, z, block);
+            }
+        }
+    }
+}
 
-        let z = i / (CHUNK_WIDTH * CHUNK_HEIGHT);
-        let y = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) / CHUNK_WIDTH;
-        let x = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) % CHUNK_WIDTH;
 
-        let mut block = BlockType::Air;
+// Returns the plants to grow on top of the ground and where.
+pub fn generate_terrain_cover(perlin: &SeededPerlin, chunk_pos: (i32, i32), blocks: &mut ChunkData) -> Vec<((usize, usize, usize), &'static Vegetation)> {
 
-        let (height, coverheight) = height_by_coords(perlin.terrain_noise, x, z, chunk_pos);
+    let mut plants = vec![];
+    let mut random = chunk_rng(perlin.seed, Stream::Vegetation, chunk_pos);
 
-        if y < height {
-            continue;
-        }
+    for z in 0 .. CHUNK_WIDTH {
+        for x in 0 .. CHUNK_WIDTH {
 
+            let (height, coverheight) = height_by_coords(perlin, x, z, chunk_pos);
+            let (world_x, world_z) = (chunk_pos.0 * CHUNK_WIDTH as i32 + x as i32, chunk_pos.1 * CHUNK_WIDTH as i32 + z as i32);
 
-        if y <= coverheight{
+            // Along borders columns take the blocks of either biome, more often of the one
+            // with the larger share, and plants thin out towards the other side.
+            let blend = blend_at(perlin, world_x, world_z);
+            let biome = blend.pick(column_value(perlin.seed, Stream::SurfaceMix, world_x, world_z));
+            let share = blend.weight(biome);
+            let biome = biome.properties();
 
-            let temperature = temperature_at(perlin, chunk_pos, x, z);
-            let humidity = humidity_at(perlin, chunk_pos, x, z);
-            
-            if temperature > 0.7 && humidity < 0.4 {
-                block = BlockType::Sand;
+            for y in height ..= coverheight.max(SEA_LEVEL) {
 
-                if y >= SEA_LEVEL {
-                    let tree_value = perlin.tree_noise.get([
-                        (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.1,
-                        (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.1]
-                    ) as f32;
-    
-                    let tree_chance = random.gen_range(-1.0 .. tree_value.abs());
-                    if tree_value > 0.2 && tree_chance > 0.96 {
-                        tree_positions.push((x,y,z));
-                    }
+                let block = if y < coverheight {
+                    biome.subsurface
                 }
-            }
-            else if y == coverheight {
-
-                if y >= SEA_LEVEL {
-
-                    block = BlockType::Grass;
-
-                    let tree_value = perlin.tree_noise.get([
-                        (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.03,
-                        (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.03]
-                    ) as f32;
-    
-                    let tree_chance = random.gen_range(-1.0 .. tree_value.abs());
-                    if tree_value > 0.2 && tree_chance > 0.6 {
-                        tree_positions.push((x,y,z));
+                else if y == coverheight && y < SEA_LEVEL {
+                    biome.underwater
+                }
+                else if y == coverheight {
+                    for vegetation in &biome.vegetation {
+                        let value = perlin.tree_noise.get([world_x as f64 * vegetation.scale, world_z as f64 * vegetation.scale]) as f32;
+
+                        let chance = random.gen_range(-1.0 .. value.abs());
+                        let rarity = 1.0 - (1.0 - vegetation.rarity) * share;
+                        if value > 0.2 && chance > rarity {
+                            plants.push(((x, y, z), vegetation));
+                            break;
+                        }
                     }
+
+                    biome.surface
                 }
                 else {
-                    block = BlockType::Dirt;
-                }
-            }
-            else {
-                block = BlockType::Dirt;
+                    BlockType::WATER
+                };
+
+                blocks.set(x, y, z, block);
             }
         }
-        else if y <= SEA_LEVEL {
-            block = BlockType::Water;
-        }
-
-
-        let index = x + y * CHUNK_WIDTH + z * CHUNK_WIDTH * CHUNK_HEIGHT;
-        blocks[index] = block;
     }
 
-    for pos in tree_positions.iter() {
-        let temperature = temperature_at(perlin, chunk_pos, pos.0, pos.2);
-        let humidity = humidity_at(perlin, chunk_pos, pos.0, pos.2);
-
-        if temperature > 0.65 && humidity < 0.4 {
-            add_cactus(random.gen_range(2..5), pos.0, pos.1, pos.2, blocks);
-        }
-        else {
-            add_tree(random.gen_range(3..6), chunk_pos, pos.0, pos.1, pos.2, world_map, blocks);
-        }
-    }
+    plants
 }
 
 
-fn generate_ore(random: &mut StdRng, blocks: &mut [BlockType; CHUNK_VOL]) {
-    
-    let fillings = random.gen_range(0 .. 50);
-
-    for _ in 0 .. fillings {
-
-        // Random walk may walk back on itself, so expect slightly pessimized ore count.
-        let neighbours = random.gen_range(1 .. 7);
-        let mut pos = random.gen_range(0 .. CHUNK_VOL) as i32;
-
-        if blocks[pos as usize] != BlockType::Stone {
-            continue;
-        }
-
-        blocks[pos as usize] = BlockType::OreStoneGold;
+pub fn generate_plants(
+    random: &mut WorldRng,
+    chunk_pos: (i32, i32),
+    plants: &[((usize, usize, usize), &Vegetation)],
+    blocks: &mut ChunkData,
+    pending: &mut HashMap<(i32, i32), PendingBlocks>,
+) {
+    // A cave may have opened up under the plant.
+    let plants: Vec<_> = plants.iter().filter(|(pos, _)| blocks.get(pos.0, pos.1, pos.2).is_solid()).collect();
 
-        for _ in 0 .. neighbours {
+    let mut writer = BlockWriter::new(chunk_pos, blocks, pending);
 
-            let sub = random.gen_bool(0.5);
-            let xyz: f32 = random.gen();
+    for (pos, vegetation) in plants {
+        let height = random.gen_range(vegetation.height.0 .. vegetation.height.1);
 
-            if xyz < 0.33 {
-                // Walk along the x-axis.
-                if sub {
-                    pos = pos - 1;
-                }
-                else {
-                    pos = pos + 1;
-                }
-                if pos < CHUNK_VOL as i32 && pos > 0 {
-                    blocks[pos as usize] = BlockType::OreStoneGold;
-                }
-            }
-            else if xyz < 0.66 {
-                // Walk along the y-axis.
-                if sub {
-                    pos = pos + CHUNK_WIDTH as i32;
-                }
-                else {
-                    pos = pos - CHUNK_WIDTH as i32;
-                }
-                if pos < CHUNK_VOL as i32 && pos > 0 {
-                    blocks[pos as usize] = BlockType::OreStoneGold;
-                }
-            }
-            else {
-                // Walk along the z-axis.
-                if sub {
-                    pos = pos - (CHUNK_WIDTH * CHUNK_HEIGHT) as i32;
-                }
-                else {
-                    pos = pos + (CHUNK_WIDTH * CHUNK_HEIGHT) as i32;
-                }
-                if pos < CHUNK_VOL as i32 && pos > 0 {
-                    blocks[pos as usize] = BlockType::OreStoneGold;
-                }
-            }
+        match vegetation.feature {
+            Feature::Cactus => add_cactus(height, pos.0, pos.1, pos.2, &mut writer),
+            Feature::Tree => add_tree(height, pos.0, pos.1, pos.2, &mut writer),
         }
     }
 }
 
 
-fn temperature_at(perlin: &Res<SeededPerlin>, chunk_pos: (i32, i32), x: usize, z: usize) -> f32 {
-    perlin.temperature_noise.get([
-        (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.001,
-        (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.001
-    ]) as f32 * 10.0
-}
+// Height of the ground at a column of the world, or of the sea above it.
+pub fn surface_height(perlin: &SeededPerlin, x: i32, z: i32) -> usize {
+    if let WorldPreset::Superflat(layers) = &perlin.preset {
+        return layers.iter().map(|(_, thickness)| thickness).sum();
+    }
 
+    let width = CHUNK_WIDTH as i32;
+    let (_, cover_height) = height_by_coords(perlin, x.rem_euclid(width) as usize, z.rem_euclid(width) as usize, (x.div_euclid(width), z.div_euclid(width)));
 
-fn humidity_at(perlin: &Res<SeededPerlin>, chunk_pos: (i32, i32), x: usize, z: usize) -> f32 {
-    perlin.moisture_noise.get([
-        (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.001,
-        (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.001
-    ]) as f32 * 10.0
+    cover_height.max(SEA_LEVEL) + 1
 }
 
 
-fn height_by_coords(perlin: Perlin, x: usize, z: usize, chunk_pos: (i32, i32)) -> (usize, usize) {
+// Height of the stone and of the ground covering it. The biomes around raise the terrain
+// and stretch its hills.
+fn height_by_coords(perlin: &SeededPerlin, x: usize, z: usize, chunk_pos: (i32, i32)) -> (usize, usize) {
 
-    let octave0 = perlin.get([
+    let noise = perlin.terrain_noise;
+
+    let octave0 = noise.get([
         (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.001,
         (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.001]
     ) as f32 * 30.0;
 
-    let octave1 = perlin.get([
+    let octave1 = noise.get([
         (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.01,
         (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.01]
     ) as f32 * 10.0;
 
-    let octave2 = perlin.get([
+    let octave2 = noise.get([
         (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.06,
         (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.06]
     ) as f32 * 4.0;
 
-    let octave2_cover = perlin.get([
+    let octave2_cover = noise.get([
         (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * 0.05,
         (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * 0.05]
     ) as f32 * 2.0;
 
-    (
-        (octave0 + octave1 + octave2 + 60.0).floor() as usize,
-        (octave0 + octave1 + octave2_cover + 64.0).floor() as usize,
-    )
+    let blend = blend_at(perlin, chunk_pos.0 * CHUNK_WIDTH as i32 + x as i32, chunk_pos.1 * CHUNK_WIDTH as i32 + z as i32);
+    let (elevation, relief) = (blend.mix(|biome| biome.elevation), blend.mix(|biome| biome.relief));
+
+    let (height, cover_height) = match perlin.preset {
+        // Only the land is stretched, valleys and seas stay as they are. The smallest octave
+        // is left alone so the ground keeps its usual thickness.
+        WorldPreset::Amplified => {
+            let land = octave0 + octave1 * relief;
+            let land = if land > 0.0 { land * AMPLIFICATION } else { land };
+
+            (land + octave2 * relief + elevation + 60.0, land + octave2_cover * relief + elevation + 64.0)
+        }
+        // The sea floor rises above the sea only where the island noise is high, with
+        // gentler hills on top.
+        WorldPreset::Islands => {
+            let island = noise.get([
+                (x as f64 + chunk_pos.0 as f64 * CHUNK_WIDTH as f64) * ISLAND_SCALE,
+                (z as f64 + chunk_pos.1 as f64 * CHUNK_WIDTH as f64) * ISLAND_SCALE]
+            ) as f32 * ISLAND_DEPTH;
+
+            (
+                island + (octave1 + octave2) * relief * 0.5 + elevation + 48.0,
+                island + (octave1 + octave2_cover) * relief * 0.5 + elevation + 52.0,
+            )
+        }
+        _ => (
+            octave0 + (octave1 + octave2) * relief + elevation + 60.0,
+            octave0 + (octave1 + octave2_cover) * relief + elevation + 64.0,
+        ),
+    };
+
+    // The bottom of the world stays bedrock.
+    (height.floor().clamp(1.0, MAX_GROUND) as usize, cover_height.floor().clamp(1.0, MAX_GROUND) as usize)
 }
 
 
 pub fn generate_water_chunk_mesh(
-    world_map: &mut ResMut<WorldMap>,
+    chunks: &HashMap<(i32,i32), ChunkData>,
     position: (i32, i32),
+    section: usize,
+    greedy: bool,
 ) -> Mesh {
-    let mut mesh = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::default()
-    );
+    let mut buffers = MeshBuffers::default();
+
+    // Flat water surfaces are merged like any other faces. The surface of a source sits
+    // a bit below the top of the block.
+    mesh_faces(&mut buffers, section, &[Face::Top], -0.125, greedy, |face, block_position| {
+        water_face(chunks, position, face, block_position)
+    });
+    mesh_faces(&mut buffers, section, &[Face::Bottom], 0.0, greedy, |face, block_position| {
+        water_face(chunks, position, face, block_position)
+    });
 
-    let mut verticies: Vec<[f32; 3]> = vec![];
-    let mut indices: Vec<u32> = vec![];
-    let mut uvs: Vec<Vec2> = vec![];
+    // Surfaces sloping down the flow, and sides, get a quad per block.
+    for block_position in section_cells(section) {
+        add_water_block(&mut buffers, chunks, position, block_position);
+    }
 
-    for i in 0..CHUNK_VOL {
-        let z = i / (CHUNK_WIDTH*CHUNK_HEIGHT);
-        let y = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) / CHUNK_WIDTH;
-        let x = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) % CHUNK_WIDTH;
+    // Water uses a standard material, which takes the vertex colour as is.
+    for color in &mut buffers.colors {
+        let brightness = light_brightness(color[1].max(color[2]));
+        *color = [brightness, brightness, brightness, 1.0];
+    }
 
-        generate_water_block(&mut verticies, &mut indices, &mut uvs, &world_map.chunks, &(x as i32,y as i32,z as i32), &position);
+    // Transparent meshes are sorted by their origin, which is moved to the middle of the section
+    // so water of neighbouring sections is drawn back to front.
+    let origin = water_mesh_origin(section);
+    for vertex in &mut buffers.verticies {
+        *vertex = (Vec3::from_array(*vertex) - origin).to_array();
     }
 
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, verticies);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-    mesh.insert_indices(mesh::Indices::U32(indices));
+    buffers.into_mesh()
+}
+
+
+// Where a section's water mesh sits relative to its chunk.
+fn water_mesh_origin(section: usize) -> Vec3 {
+    Vec3::new(CHUNK_WIDTH as f32 / 2.0, (section * SECTION_HEIGHT + SECTION_HEIGHT / 2) as f32, CHUNK_WIDTH as f32 / 2.0)
+}
+
+// A single block centred on the origin, for blocks that aren't part of a chunk.
+pub fn generate_block_mesh(block: BlockType) -> Mesh {
+    let mut buffers = MeshBuffers::default();
+
+    for face in Face::ALL {
+        add_face(&mut buffers, face, [-0.5; 3], (1, 1), FaceShading { block, ao: [1.0; 4], light: FULL_SKY_LIGHT, tint: [1.0; 3] });
+    }
 
-    mesh
+    buffers.into_mesh()
 }
 
+
 pub fn generate_chunk_mesh(
-    world_map: &mut ResMut<WorldMap>,
+    chunks: &HashMap<(i32,i32), ChunkData>,
+    tints: &ChunkTints,
     position: (i32, i32),
+    section: usize,
+    greedy: bool,
 ) -> Mesh {
-    let mut mesh = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::default()
-    );
-
-    let mut verticies: Vec<[f32; 3]> = vec![];
-    let mut indices: Vec<u32> = vec![];
-    let mut uvs: Vec<Vec2> = vec![];
-    let mut colors: Vec<[f32; 4]> = vec![];
-
-    for i in 0..CHUNK_VOL {
-        let z = i / (CHUNK_WIDTH*CHUNK_HEIGHT);
-        let y = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) / CHUNK_WIDTH;
-        let x = (i - (z * CHUNK_WIDTH*CHUNK_HEIGHT)) % CHUNK_WIDTH;
-
-        generate_block(&mut verticies, &mut indices, &mut uvs, &world_map.chunks, &(x as i32,y as i32,z as i32), &position);
-    }
+    let mut buffers = MeshBuffers::default();
+
+    mesh_faces(&mut buffers, section, &Face::ALL, 0.0, greedy, |face, block_position| {
+        block_face(chunks, tints, position, face, block_position)
+    });
 
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, verticies);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-    mesh.insert_indices(mesh::Indices::U32(indices));
+    buffers.into_mesh()
+}
 
-    calculate_ao(&mut colors, position, &world_map.chunks);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
 
-    mesh
+#[derive(Default)]
+struct MeshBuffers {
+    verticies: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    uvs: Vec<Vec2>,
+    tiles: Vec<[f32; 4]>,
+    colors: Vec<[f32; 4]>,
+    tints: Vec<[f32; 3]>,
 }
 
-fn calculate_ao(
-    colors: &mut Vec<[f32; 4]>,
-    chunk_position: (i32, i32),
-    chunks: &HashMap<(i32,i32), [BlockType; CHUNK_VOL]>,
+
+impl MeshBuffers {
+    fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default()
+        );
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.verticies);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(ATTRIBUTE_ATLAS_TILE, self.tiles);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_attribute(ATTRIBUTE_TINT, self.tints);
+        mesh.insert_indices(mesh::Indices::U32(self.indices));
+
+        mesh
+    }
+}
+
+
+// Emit the visible faces of a section, either one quad per block face or with
+// neighbouring faces that look the same merged into larger quads.
+fn mesh_faces(
+    buffers: &mut MeshBuffers,
+    section: usize,
+    faces: &[Face],
+    offset_y: f32,
+    greedy: bool,
+    face_at: impl Fn(Face, (i32, i32, i32)) -> Option<FaceShading>,
 ) {
-    for index in 0..CHUNK_VOL {
-        if !chunks[&chunk_position][index].is_transparent() {
-            let z = (index / (CHUNK_WIDTH*CHUNK_HEIGHT)) as i32;
-            let y = ((index - (z as usize * CHUNK_WIDTH*CHUNK_HEIGHT)) / CHUNK_WIDTH) as i32;
-            let x = ((index - (z as usize * CHUNK_WIDTH*CHUNK_HEIGHT)) % CHUNK_WIDTH) as i32;
-
-            if block_at_position(chunks, (x + 1, y, z), chunk_position).is_transparent() {
-                let neighbors = [
-                    block_at_position(chunks, (x + 1, y, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z - 1), chunk_position),
-                ];
-                let darks = side_ao(neighbors);
-                colors.append(&mut vec![[darks[0], darks[0], darks[0], 1.],
-                                        [darks[1], darks[1], darks[1], 1.],
-                                        [darks[2], darks[2], darks[2], 1.],
-                                        [darks[3], darks[3], darks[3], 1.]]);
-            }
+    if greedy {
+        greedy_faces(buffers, section, faces, offset_y, face_at);
+        return;
+    }
 
-            if block_at_position(chunks, (x - 1, y, z), chunk_position).is_transparent(){
-        
-                let neighbors = [
-                    block_at_position(chunks, (x - 1, y, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y, z - 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z + 1), chunk_position),
-                ];
-                let darks = side_ao(neighbors);
-                colors.append(&mut vec![[darks[0], darks[0], darks[0], 1.],
-                                        [darks[1], darks[1], darks[1], 1.],
-                                        [darks[2], darks[2], darks[2], 1.],
-                                        [darks[3], darks[3], darks[3], 1.]]);
-            }
-            
-            if block_at_position(chunks, (x, y, z - 1), chunk_position).is_transparent() {
-
-                let neighbors = [
-                    block_at_position(chunks, (x - 1, y, z - 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x, y + 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z - 1), chunk_position),
-                ];
-                let darks = side_ao(neighbors);
-                colors.append(&mut vec![[darks[0], darks[0], darks[0], 1.],
-                                        [darks[1], darks[1], darks[1], 1.],
-                                        [darks[2], darks[2], darks[2], 1.],
-                                        [darks[3], darks[3], darks[3], 1.]]);
+    for (x, y, z) in section_cells(section) {
+        for &face in faces {
+            if let Some(shading) = face_at(face, (x, y, z)) {
+                add_face(buffers, face, [x as f32, y as f32 + offset_y, z as f32], (1, 1), shading);
             }
+        }
+    }
+}
 
-            if block_at_position(chunks, (x, y, z + 1), chunk_position).is_transparent() {
-                let neighbors = [
-                    block_at_position(chunks, (x + 1, y, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x, y + 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z + 1), chunk_position),
-                ];
-                let darks = side_ao(neighbors);
-                colors.append(&mut vec![[darks[0], darks[0], darks[0], 1.],
-                                        [darks[1], darks[1], darks[1], 1.],
-                                        [darks[2], darks[2], darks[2], 1.],
-                                        [darks[3], darks[3], darks[3], 1.]]);
-            }
 
-            if block_at_position(chunks, (x, y - 1, z), chunk_position).is_transparent() {
-        
-                let neighbors = [
-                    block_at_position(chunks, (x - 1, y - 1, z), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z), chunk_position),
-                    block_at_position(chunks, (x + 1, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x, y - 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y - 1, z - 1), chunk_position),
-                ];
-                let darks = side_ao(neighbors);
-                colors.append(&mut vec![[darks[0], darks[0], darks[0], 1.],
-                                        [darks[1], darks[1], darks[1], 1.],
-                                        [darks[2], darks[2], darks[2], 1.],
-                                        [darks[3], darks[3], darks[3], 1.]]); 
+// Everything that decides how a face looks. Only faces that look the same are merged.
+#[derive(PartialEq, Copy, Clone)]
+struct FaceShading {
+    block: BlockType,
+    ao: [f32; 4],
+    // Packed light of the block in front of the face.
+    light: u8,
+    tint: [f32; 3],
+}
+
+
+// Shading of a solid block's face, if it is visible.
+fn block_face(
+    chunks: &HashMap<(i32,i32), ChunkData>,
+    tints: &ChunkTints,
+    chunk_position: (i32, i32),
+    face: Face,
+    block_position: (i32, i32, i32),
+) -> Option<FaceShading> {
+    let block = block_at_position(chunks, block_position, chunk_position);
+    if block.is_transparent() {
+        return None;
+    }
+
+    let (x, y, z) = block_position;
+    let (nx, ny, nz) = face.normal();
+    if !block_at_position(chunks, (x + nx, y + ny, z + nz), chunk_position).is_transparent() {
+        return None;
+    }
+
+    let neighbours = face.ao_neighbours().map(|(dx, dy, dz)| {
+        block_at_position(chunks, (x + dx, y + dy, z + dz), chunk_position)
+    });
+
+    let light = light_at_position(chunks, (x + nx, y + ny, z + nz), chunk_position);
+    let tint = tints.tint(block, face, x as usize, z as usize);
+    Some(FaceShading { block, ao: side_ao(neighbours), light, tint })
+}
+
+
+// The top of a water block that isn't covered by more water, where it is flat at source
+// height, or the bottom of one over air or another block that can be seen through.
+fn water_face(
+    chunks: &HashMap<(i32,i32), ChunkData>,
+    chunk_position: (i32, i32),
+    face: Face,
+    block_position: (i32, i32, i32),
+) -> Option<FaceShading> {
+    let block = block_at_position(chunks, block_position, chunk_position);
+    if !is_water(block) || (face == Face::Top && !is_flat_water(chunks, chunk_position, block_position)) {
+        return None;
+    }
+
+    let (x, y, z) = block_position;
+    let (nx, ny, nz) = face.normal();
+    let neighbour = block_at_position(chunks, (x + nx, y + ny, z + nz), chunk_position);
+    if is_water(neighbour) || (face == Face::Bottom && !neighbour.is_transparent()) {
+        return None;
+    }
+
+    let light = light_at_position(chunks, (x + nx, y + ny, z + nz), chunk_position);
+    Some(FaceShading { block: block.base(), ao: [1.0; 4], light, tint: [1.0; 3] })
+}
+
+
+fn is_flat_water(chunks: &HashMap<(i32,i32), ChunkData>, chunk_position: (i32, i32), block_position: (i32, i32, i32)) -> bool {
+    let source_height = water_height(BlockType::WATER);
+    water_corner_heights(chunks, chunk_position, block_position).iter().flatten().all(|height| *height == source_height)
+}
+
+
+// Height of the water surface at the corners of a water block, by (x, z) of the corner. Each
+// corner is the average of the water blocks around it, so the surface slopes along the flow.
+fn water_corner_heights(
+    chunks: &HashMap<(i32,i32), ChunkData>,
+    chunk_position: (i32, i32),
+    block_position: (i32, i32, i32),
+) -> [[f32; 2]; 2] {
+    let (x, y, z) = block_position;
+    let mut heights = [[0.0; 2]; 2];
+
+    for (cx, column) in heights.iter_mut().enumerate() {
+        for (cz, height) in column.iter_mut().enumerate() {
+            let around = (x + cx as i32 - 1..=x + cx as i32)
+                .flat_map(|nx| (z + cz as i32 - 1..=z + cz as i32).map(move |nz| (nx, nz)))
+                .filter(|&(nx, nz)| is_water(block_at_position(chunks, (nx, y, nz), chunk_position)));
+
+            let (mut total, mut count) = (0.0, 0);
+            for (nx, nz) in around {
+                // Water below water fills its block up to the top.
+                if is_water(block_at_position(chunks, (nx, y + 1, nz), chunk_position)) {
+                    (total, count) = (1.0, 1);
+                    break;
+                }
+                total += water_height(block_at_position(chunks, (nx, y, nz), chunk_position));
+                count += 1;
             }
 
-            if block_at_position(chunks, (x, y + 1, z), chunk_position).is_transparent() {
-        
-                let neighbors = [
-                    block_at_position(chunks, (x, y + 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z + 1), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z), chunk_position),
-                    block_at_position(chunks, (x - 1, y + 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x, y + 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z - 1), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z), chunk_position),
-                    block_at_position(chunks, (x + 1, y + 1, z + 1), chunk_position),   
-                ];
-                let darks = side_ao(neighbors);
-                colors.append(&mut vec![
-                    [darks[0], darks[0], darks[0], 1.],
-                    [darks[1], darks[1], darks[1], 1.],
-                    [darks[2], darks[2], darks[2], 1.],
-                    [darks[3], darks[3], darks[3], 1.],
-                ]);
+            *height = total / count as f32;
+        }
+    }
+
+    heights
+}
+
+
+// The sloped top and the sides of a water block. Sides are drawn against air and other blocks
+// that can be seen through, never against water or opaque blocks.
+fn add_water_block(
+    buffers: &mut MeshBuffers,
+    chunks: &HashMap<(i32,i32), ChunkData>,
+    chunk_position: (i32, i32),
+    block_position: (i32, i32, i32),
+) {
+    let block = block_at_position(chunks, block_position, chunk_position);
+    if !is_water(block) {
+        return;
+    }
+
+    let (x, y, z) = block_position;
+    let heights = water_corner_heights(chunks, chunk_position, block_position);
+    let flat = is_flat_water(chunks, chunk_position, block_position);
+
+    for face in [Face::Right, Face::Left, Face::Back, Face::Front, Face::Top] {
+        let (nx, ny, nz) = face.normal();
+        let neighbour = block_at_position(chunks, (x + nx, y + ny, z + nz), chunk_position);
+        if is_water(neighbour) || (face != Face::Top && !neighbour.is_transparent()) || (face == Face::Top && flat) {
+            continue;
+        }
+
+        let light = light_at_position(chunks, (x + nx, y + ny, z + nz), chunk_position);
+        let shading = FaceShading { block: block.base(), ao: [1.0; 4], light, tint: [1.0; 3] };
+        add_face(buffers, face, [x as f32, y as f32, z as f32], (1, 1), shading);
+
+        // Bring the top corners of the quad down to the water surface.
+        let first = buffers.verticies.len() - 4;
+        for (vertex, corner) in buffers.verticies[first..].iter_mut().zip(face.corners()) {
+            if corner[1] == 1.0 {
+                vertex[1] = y as f32 + heights[corner[0] as usize][corner[2] as usize];
             }
         }
     }
 }
 
+
+// Add a quad covering `size` blocks of the face, along its u and v axes, starting at `origin`.
+fn add_face(
+    buffers: &mut MeshBuffers,
+    face: Face,
+    origin: [f32; 3],
+    size: (usize, usize),
+    shading: FaceShading,
+) {
+    let (du, dv) = (size.0 as f32, size.1 as f32);
+    let mut scale = [1.0; 3];
+    scale[face.u_axis()] = du;
+    scale[face.v_axis()] = dv;
+
+    for corner in face.corners() {
+        buffers.verticies.push([
+            origin[0] + corner[0] * scale[0],
+            origin[1] + corner[1] * scale[1],
+            origin[2] + corner[2] * scale[2],
+        ]);
+    }
+    add_indices(&mut buffers.indices, (buffers.verticies.len() - 4) as u32);
+
+    buffers.tiles.extend([shading.block.tile(face); 4]);
+
+    // Counted in tiles so the shader can repeat the texture, corners going (max u, min v),
+    // (min u, min v), (min u, max v), (max u, max v) like a single block's face always has.
+    buffers.uvs.extend([Vec2::new(du, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, dv), Vec2::new(du, dv)]);
+
+    // AO in red, sky and block light in green and blue, combined in the shader.
+    let (sky, block) = light_levels(shading.light);
+    let (sky, block) = (sky as f32 / MAX_LIGHT as f32, block as f32 / MAX_LIGHT as f32);
+    buffers.colors.extend(shading.ao.map(|dark| [dark, sky, block, 1.0]));
+    buffers.tints.extend([shading.tint; 4]);
+}
+
+
+// Same falloff as the chunk shader, for meshes drawn with other materials.
+fn light_brightness(light: f32) -> f32 {
+    0.8f32.powf((1.0 - light) * MAX_LIGHT as f32).max(0.05)
+}
+
+
+// Section cells in the order the naive mesher emits faces.
+fn section_cells(section: usize) -> impl Iterator<Item = (i32, i32, i32)> {
+    let bottom = section * SECTION_HEIGHT;
+
+    (0..CHUNK_WIDTH).flat_map(move |z| {
+        (bottom..bottom + SECTION_HEIGHT).flat_map(move |y| {
+            (0..CHUNK_WIDTH).map(move |x| (x as i32, y as i32, z as i32))
+        })
+    })
+}
+
 fn ao_value(side1: bool, corner: bool, side2: bool) -> u32 {
     match (side1, corner, side2) {
         (true, _, true) => 0,
@@ -466,225 +615,125 @@ fn side_ao(neighbors: [BlockType; 8]) -> [f32; 4] {
     ]
 }
 
-pub fn build_water_chunk(
-    commands: &mut Commands,
-    world_map: &mut ResMut<WorldMap>,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    position: (i32, i32),
-) {
-    if world_map.water_chunk_entities.contains_key(&position) { // if there's a spawned chunk, we remove it
-        commands.entity(world_map.water_chunk_entities[&position]).despawn();
-        world_map.water_chunk_entities.remove(&position);
-    }
-
-    // let material_handle = materials.add(Color::rgba(0.5, 0.5, 1.0, 0.75).into());
-
-    let material_handle = materials.add(
-        StandardMaterial {
-            base_color: Color::rgba(0.25, 0.5, 1.0, 0.75),
-            alpha_mode: AlphaMode::Blend,
-            unlit: true,
-            ..default()
-        }
-    );
-
-    let mesh = generate_water_chunk_mesh(world_map, position);
+// Meshes for one chunk section, built off the main thread.
+pub struct SectionMeshes {
+    pub section: usize,
+    pub chunk: Option<(Mesh, Collider)>,
+    pub water: Option<Mesh>,
+}
 
-    let water_chunk = commands.spawn(MaterialMeshBundle {
-        mesh: meshes.add(mesh.clone()),
-        material: material_handle,
-        transform: Transform::from_translation(Vec3::new(position.0 as f32 * CHUNK_WIDTH as f32, 0.0, position.1 as f32  * CHUNK_WIDTH as f32)),
-        ..default()
-    }).id();
 
-    world_map.water_chunk_entities.insert(position, water_chunk);
+pub struct ChunkMeshes {
+    pub position: (i32, i32),
+    // Revision of the chunk data the meshes were built from.
+    pub revision: u32,
+    pub sections: Vec<SectionMeshes>,
 }
 
-pub fn build_chunk(
-    commands: &mut Commands,
-    world_map: &mut ResMut<WorldMap>,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-    position: (i32, i32),
-) -> bool {
-    if world_map.chunk_entities.contains_key(&position) { // if there's a spawned chunk, we remove it
-        commands.entity(world_map.chunk_entities[&position]).despawn();
-        world_map.chunk_entities.remove(&position);
-    }
 
-    if world_map.water_chunk_entities.contains_key(&position) {
-        commands.entity(world_map.water_chunk_entities[&position]).despawn();
-        world_map.water_chunk_entities.remove(&position);
-    }
+// Copy of a chunk and its eight neighbours, everything meshing the chunk looks at.
+pub fn snapshot_chunks(world_map: &WorldMap, position: (i32, i32)) -> HashMap<(i32, i32), ChunkData> {
+    let mut snapshot = HashMap::new();
 
-    if world_map.reserved_chunk_data.contains_key(&position) {
-        let mut blocks = world_map.chunks[&position];
-        for index in 0..CHUNK_VOL {
-            if world_map.reserved_chunk_data[&position][index] != BlockType::Air {
-                blocks[index] = world_map.reserved_chunk_data[&position][index];
+    for x in -1..=1 {
+        for z in -1..=1 {
+            let neighbour = (position.0 + x, position.1 + z);
+            if let Some(blocks) = world_map.chunks.get(&neighbour) {
+                snapshot.insert(neighbour, blocks.clone());
             }
         }
-        world_map.chunks.insert(position, blocks);
-        world_map.reserved_chunk_data.remove(&position);
     }
 
-    let texture_handle = asset_server.load("blocks.png");
-    let material_handle = materials.add(StandardMaterial {
-        base_color_texture: Some(texture_handle),
-        unlit: true,
-        ..default()
-    });
-
-    let mesh = generate_chunk_mesh(world_map, position);
-
-    let chunk = commands.spawn(MaterialMeshBundle {
-        mesh: meshes.add(mesh.clone()),
-        material: material_handle,
-        transform: Transform::from_translation(Vec3::new(position.0 as f32 * CHUNK_WIDTH as f32, 0.0, position.1 as f32  * CHUNK_WIDTH as f32)),
-        ..default()
-    })
-    .insert(Collider::from_bevy_mesh(&mesh, &ComputedColliderShape::TriMesh).unwrap())
-    .insert(Friction {
-        coefficient: 0.0,
-        combine_rule: CoefficientCombineRule::Min,
-    }).id();
-
-    world_map.chunk_entities.insert(position, chunk);
-
-    build_water_chunk(commands, world_map, meshes, materials, position);
-
-    true
-}
-
-fn generate_water_block(
-    verticies: &mut Vec<[f32; 3]>,
-    indices: &mut Vec<u32>,
-    uvs: &mut Vec<Vec2>,
-    chunks: &HashMap<(i32,i32), [BlockType; CHUNK_VOL]>,
-    block_position: &(i32,i32,i32),
-    chunk_position: &(i32, i32),
-) {
-    let block = block_at_position(chunks, *block_position, *chunk_position);
-
-    let (x,y,z) = (block_position.0 as f32, block_position.1 as f32, block_position.2 as f32);
-    if block != BlockType::Water {
-        return;
-    }
-
-    //top side
-    if block_at_position(chunks, (x as i32, y as i32 + 1, z as i32), *chunk_position).is_transparent()
-    && block_at_position(chunks, (x as i32, y as i32 + 1, z as i32), *chunk_position) != BlockType::Water {
-
-        verticies.extend([
-            [x + 1.0, y + 1.0 - 0.125, z + 1.0],
-            [x + 1.0, y + 1.0 - 0.125, z + 0.0],
-            [x + 0.0, y + 1.0 - 0.125, z + 0.0],
-            [x + 0.0, y + 1.0 - 0.125, z + 1.0]
-        ]);
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().top);
-    }
+    snapshot
 }
 
-fn generate_block(
-    verticies: &mut Vec<[f32; 3]>,
-    indices: &mut Vec<u32>,
-    uvs: &mut Vec<Vec2>,
-    chunks: &HashMap<(i32,i32), [BlockType; CHUNK_VOL]>,
-    block_position: &(i32,i32,i32),
-    chunk_position: &(i32, i32),
-) {
-    let block = block_at_position(chunks, *block_position, *chunk_position);
-
-    let (x,y,z) = (block_position.0 as f32, block_position.1 as f32, block_position.2 as f32);
-    if block.is_transparent() {
-        return;
-    }
-
-    // right side
-    if block_at_position(chunks, (x as i32 + 1, y as i32, z as i32), *chunk_position).is_transparent() {
-
-        verticies.extend([
-            [x + 1.0, y + 1.0, z + 0.0],
-            [x + 1.0, y + 1.0, z + 1.0],
-            [x + 1.0, y + 0.0, z + 1.0],
-            [x + 1.0, y + 0.0, z + 0.0],
-        ]);
-
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().right);
-    }
-
-    //left side
-    if block_at_position(chunks, (x as i32 - 1, y as i32, z as i32), *chunk_position).is_transparent() {
-
-        verticies.extend([
-            [x + 0.0, y + 1.0, z + 1.0],
-            [x + 0.0, y + 1.0, z + 0.0],
-            [x + 0.0, y + 0.0, z + 0.0],
-            [x + 0.0, y + 0.0, z + 1.0]
-        ]);
 
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().left);
-    }
-
-    //back side
-    if block_at_position(chunks, (x as i32, y as i32, z as i32 - 1), *chunk_position).is_transparent() {
+pub fn mesh_chunk_sections(
+    chunks: &HashMap<(i32, i32), ChunkData>,
+    perlin: &SeededPerlin,
+    position: (i32, i32),
+    revision: u32,
+    sections: &[usize],
+    greedy: bool,
+) -> ChunkMeshes {
+    let mut section_meshes = vec![];
+    let tints = ChunkTints::new(perlin, position);
+
+    for &section in sections {
+        let mut meshes = SectionMeshes { section, chunk: None, water: None };
+
+        if !chunks[&position].section(section).is_empty() {
+            let mesh = generate_chunk_mesh(chunks, &tints, position, section, greedy);
+
+            // Sections buried under other blocks have no visible faces at all.
+            if mesh.count_vertices() > 0 {
+                let collider = Collider::from_bevy_mesh(&mesh, &ComputedColliderShape::TriMesh).unwrap();
+                meshes.chunk = Some((mesh, collider));
+            }
 
-        verticies.extend([
-            [x + 0.0, y + 1.0, z + 0.0],
-            [x + 1.0, y + 1.0, z + 0.0],
-            [x + 1.0, y + 0.0, z + 0.0],
-            [x + 0.0, y + 0.0, z + 0.0],
-        ]);
+            let water_mesh = generate_water_chunk_mesh(chunks, position, section, greedy);
+            if water_mesh.count_vertices() > 0 {
+                meshes.water = Some(water_mesh);
+            }
+        }
 
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().back);
+        section_meshes.push(meshes);
     }
 
-    //front side
-    if block_at_position(chunks, (x as i32, y as i32, z as i32 + 1), *chunk_position).is_transparent(){
+    ChunkMeshes { position, revision, sections: section_meshes }
+}
 
-        verticies.extend([
-            [x + 1.0, y + 1.0, z + 1.0],
-            [x + 0.0, y + 1.0, z + 1.0],
-            [x + 0.0, y + 0.0, z + 1.0],
-            [x + 1.0, y + 0.0, z + 1.0],
-        ]);
 
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().front);
-    }
+pub fn spawn_chunk_meshes(
+    commands: &mut Commands,
+    world_map: &mut ResMut<WorldMap>,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    chunk_materials: &ChunkMaterials,
+    chunk_meshes: ChunkMeshes,
+) {
+    let position = chunk_meshes.position;
+    let transform = Transform::from_translation(Vec3::new(position.0 as f32 * CHUNK_WIDTH as f32, 0.0, position.1 as f32  * CHUNK_WIDTH as f32));
 
-    //bottom side
-    if block_at_position(chunks, (x as i32, y as i32 - 1, z as i32), *chunk_position).is_transparent() {
+    // Every built chunk gets an entry, even when all of its sections turn out empty.
+    world_map.chunk_entities.entry(position).or_insert([None; SECTION_COUNT]);
+    world_map.water_chunk_entities.entry(position).or_insert([None; SECTION_COUNT]);
 
-        verticies.extend([
-            [x + 0.0, y + 0.0, z + 1.0],
-            [x + 0.0, y + 0.0, z + 0.0],
-            [x + 1.0, y + 0.0, z + 0.0],
-            [x + 1.0, y + 0.0, z + 1.0]
-        ]);
+    for section_meshes in chunk_meshes.sections {
+        let section = section_meshes.section;
 
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().bottom);
-    }
+        if let Some(entity) = world_map.chunk_entities.get_mut(&position).unwrap()[section].take() {
+            commands.entity(entity).despawn();
+        }
+        if let Some(entity) = world_map.water_chunk_entities.get_mut(&position).unwrap()[section].take() {
+            commands.entity(entity).despawn();
+        }
 
-    //top side
-    if block_at_position(chunks, (x as i32, y as i32 + 1, z as i32), *chunk_position).is_transparent() {
+        if let Some((mesh, collider)) = section_meshes.chunk {
+            let chunk = commands.spawn(MaterialMeshBundle {
+                mesh: meshes.add(mesh),
+                material: chunk_materials.chunk.clone(),
+                transform,
+                ..default()
+            })
+            .insert(collider)
+            .insert(Friction {
+                coefficient: 0.0,
+                combine_rule: CoefficientCombineRule::Min,
+            }).id();
+
+            world_map.chunk_entities.get_mut(&position).unwrap()[section] = Some(chunk);
+        }
 
-        verticies.extend([
-            [x + 1.0, y + 1.0, z + 1.0],
-            [x + 1.0, y + 1.0, z + 0.0],
-            [x + 0.0, y + 1.0, z + 0.0],
-            [x + 0.0, y + 1.0, z + 1.0]
-        ]);
+        if let Some(mesh) = section_meshes.water {
+            let water_chunk = commands.spawn(MaterialMeshBundle {
+                mesh: meshes.add(mesh),
+                material: chunk_materials.water.clone(),
+                transform: transform.with_translation(transform.translation + water_mesh_origin(section)),
+                ..default()
+            }).id();
 
-        add_indices(indices, (verticies.len() - 4) as u32);
-        uvs.extend(block.uvs().top);
+            world_map.water_chunk_entities.get_mut(&position).unwrap()[section] = Some(water_chunk);
+        }
     }
 }
 
@@ -697,7 +746,7 @@ fn add_indices(
 }
 
 fn block_at_position(
-    chunks: &HashMap<(i32,i32), [BlockType; CHUNK_VOL]>,
+    chunks: &HashMap<(i32,i32), ChunkData>,
     block_position: (i32, i32, i32),
     chunk_position: (i32, i32),
 ) -> BlockType {
@@ -705,8 +754,8 @@ fn block_at_position(
     let mut new_position: (i32,i32,i32) = block_position;
     let mut new_chunk_position: (i32,i32) = chunk_position;
 
-    if block_position.1 < 0 || block_position.1 > CHUNK_HEIGHT as i32 {
-        return BlockType::Dirt;
+    if block_position.1 < 0 || block_position.1 >= CHUNK_HEIGHT as i32 {
+        return BlockType::DIRT;
     }
 
     if block_position.0 > CHUNK_WIDTH as i32 - 1 {
@@ -725,12 +774,30 @@ fn block_at_position(
         new_chunk_position.1 -= 1;
     }
 
-    if chunks.contains_key(&new_chunk_position) {
+    if let Some(blocks) = chunks.get(&new_chunk_position) {
+        return blocks.get(new_position.0 as usize, new_position.1 as usize, new_position.2 as usize);
+    }
+
+    return BlockType::DIRT;
+}
+
 
-        let index = new_position.0 + new_position.1 * CHUNK_WIDTH as i32 + new_position.2 * (CHUNK_WIDTH * CHUNK_HEIGHT) as i32;
-        return chunks[&new_chunk_position][index as usize].clone();
-        //return chunks[&new_chunk_position][new_position.0 as usize][new_position.1 as usize][new_position.2 as usize].clone();
+// Packed light of the block at a position relative to the chunk. Above the world is open sky.
+fn light_at_position(
+    chunks: &HashMap<(i32,i32), ChunkData>,
+    block_position: (i32, i32, i32),
+    chunk_position: (i32, i32),
+) -> u8 {
+    if block_position.1 >= CHUNK_HEIGHT as i32 {
+        return FULL_SKY_LIGHT;
+    }
+    if block_position.1 < 0 {
+        return 0;
     }
 
-    return BlockType::Dirt;
-}
\ No newline at end of file
+    let width = CHUNK_WIDTH as i32;
+    let chunk = (chunk_position.0 + block_position.0.div_euclid(width), chunk_position.1 + block_position.2.div_euclid(width));
+    chunks.get(&chunk)
+        .map(|blocks| blocks.light(block_position.0.rem_euclid(width) as usize, block_position.1 as usize, block_position.2.rem_euclid(width) as usize))
+        .unwrap_or_default()
+}
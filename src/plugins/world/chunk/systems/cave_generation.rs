@@ -0,0 +1,261 @@
+use noise::NoiseFn;
+use rand::Rng;
+
+use crate::plugins::world::{SeededPerlin, chunk::{components::BlockType, data::ChunkData, seeding::{Stream, chunk_rng}}};
+use crate::{CHUNK_WIDTH, CHUNK_HEIGHT};
+
+use super::{SEA_LEVEL, height_by_coords};
+
+
+// Cheese caves are where 3D noise goes above a threshold, kept this far below the surface.
+const CAVE_SCALE: f64 = 0.04;
+const CAVE_THRESHOLD: f64 = 0.45;
+const CAVE_SURFACE_DEPTH: usize = 8;
+
+// One chunk in WORM_CHANCE starts a tunnel, wandering up to WORM_MAX_LENGTH blocks.
+const WORM_CHANCE: u32 = 6;
+const WORM_MIN_LENGTH: usize = 32;
+const WORM_MAX_LENGTH: usize = 96;
+const WORM_SCALE: f64 = 0.03;
+// Chunks far enough away that their tunnels can't reach this one.
+const WORM_REACH: i32 = (WORM_MAX_LENGTH / CHUNK_WIDTH) as i32 + 1;
+
+// Blocks kept between caves and the water of the sea or lakes above them.
+const SEA_FLOOR_THICKNESS: usize = 3;
+
+
+// Carve cheese caves and worm tunnels out of the terrain. Tunnels started by nearby
+// chunks are followed into this one, so they line up across chunk borders.
+pub fn carve_caves(perlin: &SeededPerlin, chunk_pos: (i32, i32), blocks: &mut ChunkData) {
+    let ceilings = carve_ceilings(perlin, chunk_pos);
+
+    for (z, row) in ceilings.iter().enumerate() {
+        for (x, ceiling) in row.iter().enumerate() {
+            let (height, _) = height_by_coords(perlin, x, z, chunk_pos);
+            let top = (*ceiling).min(height.saturating_sub(CAVE_SURFACE_DEPTH));
+            let (wx, wz) = world_xz(chunk_pos, x, z);
+
+            for y in 1..top {
+                let density = perlin.cave_noise.get([wx * CAVE_SCALE, y as f64 * CAVE_SCALE * 1.5, wz * CAVE_SCALE]);
+                if density > CAVE_THRESHOLD {
+                    carve(blocks, x, y, z);
+                }
+            }
+        }
+    }
+
+    for source_x in chunk_pos.0 - WORM_REACH..=chunk_pos.0 + WORM_REACH {
+        for source_z in chunk_pos.1 - WORM_REACH..=chunk_pos.1 + WORM_REACH {
+            carve_worm(perlin, chunk_pos, (source_x, source_z), &ceilings, blocks);
+        }
+    }
+}
+
+
+// Follow the tunnel started by the source chunk, if any, carving the part inside this chunk.
+fn carve_worm(perlin: &SeededPerlin, chunk_pos: (i32, i32), source: (i32, i32), ceilings: &[[usize; CHUNK_WIDTH]; CHUNK_WIDTH], blocks: &mut ChunkData) {
+    let mut random = chunk_rng(perlin.seed, Stream::Tunnels, source);
+    if random.gen_range(0..WORM_CHANCE) != 0 {
+        return;
+    }
+
+    let width = CHUNK_WIDTH as f64;
+    let mut position = [
+        source.0 as f64 * width + random.gen_range(0.0..width),
+        random.gen_range(12.0..72.0),
+        source.1 as f64 * width + random.gen_range(0.0..width),
+    ];
+    let length = random.gen_range(WORM_MIN_LENGTH..=WORM_MAX_LENGTH);
+    let radius = random.gen_range(1.5..3.0);
+    let mut yaw = random.gen_range(0.0..std::f64::consts::TAU);
+    let mut pitch: f64 = 0.0;
+
+    let (min_x, min_z) = (chunk_pos.0 as f64 * width, chunk_pos.1 as f64 * width);
+
+    for step in 0..length {
+        // The noise steers the worm, so it winds smoothly instead of turning at random.
+        let turn = [position[0] * WORM_SCALE, position[1] * WORM_SCALE, position[2] * WORM_SCALE];
+        yaw += perlin.tunnel_noise.get([turn[0], turn[1], turn[2]]) * 0.3;
+        pitch = (pitch + perlin.tunnel_noise.get([turn[2], turn[0], turn[1] + step as f64 * 0.01]) * 0.1).clamp(-0.6, 0.6);
+
+        position[0] += yaw.cos() * pitch.cos();
+        position[1] += pitch.sin();
+        position[2] += yaw.sin() * pitch.cos();
+
+        if position[0] + radius < min_x || position[0] - radius > min_x + width
+            || position[2] + radius < min_z || position[2] - radius > min_z + width {
+            continue;
+        }
+
+        for (z, row) in ceilings.iter().enumerate() {
+            for (x, ceiling) in row.iter().enumerate() {
+                let (dx, dz) = (min_x + x as f64 + 0.5 - position[0], min_z + z as f64 + 0.5 - position[2]);
+                let y_from = (position[1] - radius).max(1.0) as usize;
+                let y_to = ((position[1] + radius) as usize).min(*ceiling);
+
+                for y in y_from..y_to {
+                    let dy = y as f64 + 0.5 - position[1];
+                    if dx * dx + dy * dy + dz * dz < radius * radius {
+                        carve(blocks, x, y, z);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+// Highest block caves may carve in each column. Tunnels can break through the surface,
+// except under or next to water, where enough ground is left to hold the water up.
+fn carve_ceilings(perlin: &SeededPerlin, chunk_pos: (i32, i32)) -> [[usize; CHUNK_WIDTH]; CHUNK_WIDTH] {
+    let mut ceilings = [[CHUNK_HEIGHT; CHUNK_WIDTH]; CHUNK_WIDTH];
+
+    for (z, row) in ceilings.iter_mut().enumerate() {
+        for (x, ceiling) in row.iter_mut().enumerate() {
+            let (wx, wz) = world_xz(chunk_pos, x, z);
+
+            for (nx, nz) in [(0, 0), (1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (column_chunk, cx, cz) = column_at(wx as i32 + nx, wz as i32 + nz);
+                let (height, cover_height) = height_by_coords(perlin, cx, cz, column_chunk);
+
+                if cover_height < SEA_LEVEL {
+                    *ceiling = (*ceiling).min(height.saturating_sub(SEA_FLOOR_THICKNESS));
+                }
+            }
+        }
+    }
+
+    ceilings
+}
+
+
+// Caves are carved before plants grow, so everything here is ground, and all of it but
+// bedrock and the water above it is carved.
+fn carve(blocks: &mut ChunkData, x: usize, y: usize, z: usize) {
+    let block = blocks.get(x, y, z);
+    if block != BlockType::BEDROCK && block != BlockType::WATER {
+        blocks.set(x, y, z, BlockType::AIR);
+    }
+}
+
+
+fn world_xz(chunk_pos: (i32, i32), x: usize, z: usize) -> (f64, f64) {
+    ((chunk_pos.0 * CHUNK_WIDTH as i32 + x as i32) as f64, (chunk_pos.1 * CHUNK_WIDTH as i32 + z as i32) as f64)
+}
+
+
+fn column_at(x: i32, z: i32) -> ((i32, i32), usize, usize) {
+    let width = CHUNK_WIDTH as i32;
+    ((x.div_euclid(width), z.div_euclid(width)), x.rem_euclid(width) as usize, z.rem_euclid(width) as usize)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+
+    use crate::plugins::world::chunk::{light::{Position, world_position, offset}, preset::WorldPreset, seeding::Stream};
+    use crate::plugins::world::testing::load_registries;
+
+    use super::*;
+    use super::super::{generate_terrain_shape, generate_terrain_cover};
+
+
+    const SEED: u64 = 12345;
+    const SIDES: [Position; 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+
+
+    #[test]
+    fn caves_keep_to_the_ground_above_bedrock_and_away_from_water() {
+        load_registries();
+        let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+        let mut water = 0;
+
+        for chunk_pos in (-6..6).flat_map(|x| (-6..6).map(move |z| (x, z))) {
+            let mut blocks = ChunkData::new(BlockType::AIR);
+            generate_terrain_shape(&perlin, chunk_pos, &mut blocks);
+            generate_terrain_cover(&perlin, chunk_pos, &mut blocks);
+            let before = blocks.clone();
+            carve_caves(&perlin, chunk_pos, &mut blocks);
+
+            for z in 0..CHUNK_WIDTH {
+                for y in 0..CHUNK_HEIGHT {
+                    for x in 0..CHUNK_WIDTH {
+                        let (old, new) = (before.get(x, y, z), blocks.get(x, y, z));
+                        if old == BlockType::WATER {
+                            water += 1;
+                        }
+                        if old == new {
+                            continue;
+                        }
+
+                        assert_ne!(y, 0, "chunk {:?} was carved into at the bottom", chunk_pos);
+                        assert!(old != BlockType::BEDROCK && old != BlockType::WATER, "chunk {:?} lost {:?} at {:?}", chunk_pos, old, (x, y, z));
+
+                        // No water is left hanging over a cave.
+                        assert_ne!(before.get(x, y + 1, z), BlockType::WATER, "chunk {:?} opened a cave under water at {:?}", chunk_pos, (x, y, z));
+                    }
+                }
+            }
+        }
+
+        assert!(water > 0, "no water in the tested region");
+    }
+
+
+    // Air carved by the tunnel a chunk starts, in every chunk it can reach.
+    fn worm_cells(perlin: &SeededPerlin, source: (i32, i32)) -> HashSet<Position> {
+        let mut cells = HashSet::new();
+        let ceilings = [[CHUNK_HEIGHT; CHUNK_WIDTH]; CHUNK_WIDTH];
+
+        for chunk_x in source.0 - WORM_REACH..=source.0 + WORM_REACH {
+            for chunk_z in source.1 - WORM_REACH..=source.1 + WORM_REACH {
+                let mut blocks = ChunkData::new(BlockType::STONE);
+                carve_worm(perlin, (chunk_x, chunk_z), source, &ceilings, &mut blocks);
+
+                for z in 0..CHUNK_WIDTH {
+                    for y in 0..CHUNK_HEIGHT / 2 {
+                        for x in 0..CHUNK_WIDTH {
+                            if blocks.get(x, y, z) == BlockType::AIR {
+                                cells.insert(world_position((chunk_x, chunk_z), x, y, z));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        cells
+    }
+
+
+    // Every chunk carves its own part of a tunnel, so the parts only join up into one if the
+    // chunks agree on where it goes.
+    #[test]
+    fn tunnels_line_up_across_chunk_borders() {
+        load_registries();
+        let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+        let source = (0..).map(|x| (x, 0))
+            .find(|source| chunk_rng(SEED, Stream::Tunnels, *source).gen_range(0..WORM_CHANCE) == 0)
+            .unwrap();
+        let cells = worm_cells(&perlin, source);
+
+        let chunks: HashSet<(i32, i32)> = cells.iter().map(|(x, _, z)| (x.div_euclid(CHUNK_WIDTH as i32), z.div_euclid(CHUNK_WIDTH as i32))).collect();
+        assert!(chunks.len() > 1, "the tunnel of chunk {:?} stays in one chunk", source);
+
+        let start = *cells.iter().next().unwrap();
+        let mut reached = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(cell) = queue.pop_front() {
+            for side in SIDES {
+                let next = offset(cell, side);
+                if cells.contains(&next) && reached.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        assert_eq!(reached.len(), cells.len(), "the tunnel of chunk {:?} is broken up", source);
+    }
+}
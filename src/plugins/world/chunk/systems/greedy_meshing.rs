@@ -0,0 +1,157 @@
+use crate::plugins::world::chunk::components::Face;
+use crate::{CHUNK_WIDTH, SECTION_HEIGHT};
+
+use super::{MeshBuffers, FaceShading, add_face};
+
+
+// Merge neighbouring faces of the same block type, AO and light into larger quads. Every slice of the
+// section facing the same way is turned into a mask of visible faces, then each face that hasn't
+// been merged yet grows as far as it can along u, then along v.
+pub(super) fn greedy_faces(
+    buffers: &mut MeshBuffers,
+    section: usize,
+    faces: &[Face],
+    offset_y: f32,
+    face_at: impl Fn(Face, (i32, i32, i32)) -> Option<FaceShading>,
+) {
+    let size = [CHUNK_WIDTH, SECTION_HEIGHT, CHUNK_WIDTH];
+    let bottom = (section * SECTION_HEIGHT) as i32;
+
+    for &face in faces {
+        let (u_axis, v_axis) = (face.u_axis(), face.v_axis());
+        let normal_axis = 3 - u_axis - v_axis;
+        let (width, height) = (size[u_axis], size[v_axis]);
+
+        let position = |slice: usize, u: usize, v: usize| {
+            let mut position = [0; 3];
+            position[normal_axis] = slice as i32;
+            position[u_axis] = u as i32;
+            position[v_axis] = v as i32;
+            position[1] += bottom;
+            position
+        };
+
+        for slice in 0..size[normal_axis] {
+            let mut mask = vec![None; width * height];
+            for v in 0..height {
+                for u in 0..width {
+                    let [x, y, z] = position(slice, u, v);
+                    mask[u + v * width] = face_at(face, (x, y, z));
+                }
+            }
+
+            for v in 0..height {
+                let mut u = 0;
+                while u < width {
+                    let Some(shading) = mask[u + v * width] else {
+                        u += 1;
+                        continue;
+                    };
+                    let cell = Some(shading);
+
+                    let mut du = 1;
+                    while u + du < width && mask[u + du + v * width] == cell {
+                        du += 1;
+                    }
+
+                    let mut dv = 1;
+                    while v + dv < height && (u..u + du).all(|k| mask[k + (v + dv) * width] == cell) {
+                        dv += 1;
+                    }
+
+                    for row in v..v + dv {
+                        for k in u..u + du {
+                            mask[k + row * width] = None;
+                        }
+                    }
+
+                    let [x, y, z] = position(slice, u, v);
+                    add_face(buffers, face, [x as f32, y as f32 + offset_y, z as f32], (du, dv), shading);
+
+                    u += du;
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use bevy::math::Vec2;
+
+    use crate::plugins::world::chunk::{components::BlockType, data::ChunkData, light::FULL_SKY_LIGHT};
+    use crate::plugins::world::testing::load_registries;
+
+    use super::*;
+    use super::super::generate_water_chunk_mesh;
+
+
+    fn shading(block: BlockType) -> FaceShading {
+        FaceShading { block, ao: [1.0; 4], light: FULL_SKY_LIGHT, tint: [1.0; 3] }
+    }
+
+
+    // Top faces of the bottom layer of section 0, shaded by x and z.
+    fn mesh_layer(shading_at: impl Fn(i32, i32) -> FaceShading) -> MeshBuffers {
+        let mut buffers = MeshBuffers::default();
+        greedy_faces(&mut buffers, 0, &[Face::Top], 0.0, |_, (x, y, z)| (y == 0).then(|| shading_at(x, z)));
+        buffers
+    }
+
+
+    #[test]
+    fn a_flat_plane_is_one_quad() {
+        load_registries();
+        let buffers = mesh_layer(|_, _| shading(BlockType::STONE));
+
+        assert_eq!(buffers.verticies.len(), 4);
+        // The texture repeats once per block along both sides.
+        let width = CHUNK_WIDTH as f32;
+        assert_eq!(buffers.uvs, vec![Vec2::new(width, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, width), Vec2::new(width, width)]);
+    }
+
+
+    #[test]
+    fn faces_that_look_different_are_not_merged() {
+        load_registries();
+        let half = CHUNK_WIDTH as i32 / 2;
+
+        let stone = shading(BlockType::STONE);
+
+        // Another block, AO or light on one half of the plane.
+        for other in [
+            FaceShading { block: BlockType::DIRT, ..stone },
+            FaceShading { ao: [1.0, 0.5, 1.0, 1.0], ..stone },
+            FaceShading { light: FULL_SKY_LIGHT - 0x10, ..stone },
+        ] {
+            let buffers = mesh_layer(|x, _| if x < half { stone } else { other });
+            assert_eq!(buffers.verticies.len(), 2 * 4);
+        }
+    }
+
+
+    #[test]
+    fn still_water_is_one_quad_per_section() {
+        load_registries();
+        let surface = 5;
+
+        let mut chunks = HashMap::new();
+        for chunk_pos in (-1..=1).flat_map(|x| (-1..=1).map(move |z| (x, z))) {
+            let mut blocks = ChunkData::new(BlockType::AIR);
+            for z in 0..CHUNK_WIDTH {
+                for y in 0..=surface {
+                    for x in 0..CHUNK_WIDTH {
+                        blocks.set(x, y, z, if y < surface { BlockType::STONE } else { BlockType::WATER });
+                    }
+                }
+            }
+            chunks.insert(chunk_pos, blocks);
+        }
+
+        assert_eq!(generate_water_chunk_mesh(&chunks, (0, 0), 0, true).count_vertices(), 4);
+        assert_eq!(generate_water_chunk_mesh(&chunks, (0, 0), 0, false).count_vertices(), CHUNK_WIDTH * CHUNK_WIDTH * 4);
+        assert_eq!(generate_water_chunk_mesh(&chunks, (0, 0), 1, true).count_vertices(), 0);
+    }
+}
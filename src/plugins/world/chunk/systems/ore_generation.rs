@@ -0,0 +1,138 @@
+use rand::Rng;
+
+use crate::plugins::world::chunk::{data::ChunkData, ore::{ores, OreProperties, HeightDistribution}, seeding::WorldRng};
+use crate::{CHUNK_WIDTH, CHUNK_HEIGHT};
+
+
+const STEPS: [(i32, i32, i32); 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+
+
+// Grow the veins of every ore in the ore registry through the stone of a chunk.
+pub fn generate_ores(random: &mut WorldRng, blocks: &mut ChunkData) {
+    for ore in ores() {
+        let fraction = ore.veins_per_chunk.fract();
+        let veins = ore.veins_per_chunk as usize + random.gen_bool(fraction as f64) as usize;
+
+        for _ in 0..veins {
+            add_vein(random, ore, blocks);
+        }
+    }
+}
+
+
+// A random walk from a random start, one block at a time along any of the three axes. Steps
+// out of the chunk or onto blocks the ore can't replace place nothing, so the vein comes out
+// smaller there.
+fn add_vein(random: &mut WorldRng, ore: &OreProperties, blocks: &mut ChunkData) {
+    let size = random.gen_range(ore.vein_size.0..=ore.vein_size.1);
+    let mut position = (
+        random.gen_range(0..CHUNK_WIDTH) as i32,
+        vein_height(random, ore) as i32,
+        random.gen_range(0..CHUNK_WIDTH) as i32,
+    );
+
+    for step in 0..size {
+        if step > 0 {
+            let (dx, dy, dz) = STEPS[random.gen_range(0..STEPS.len())];
+            position = (position.0 + dx, position.1 + dy, position.2 + dz);
+        }
+
+        let (x, y, z) = position;
+        if !(0..CHUNK_WIDTH as i32).contains(&x) || !(1..CHUNK_HEIGHT as i32).contains(&y) || !(0..CHUNK_WIDTH as i32).contains(&z) {
+            continue;
+        }
+
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if ore.replaces.contains(&blocks.get(x, y, z)) {
+            blocks.set(x, y, z, ore.block);
+        }
+    }
+}
+
+
+fn vein_height(random: &mut WorldRng, ore: &OreProperties) -> usize {
+    let (min, max) = ore.height;
+    let range = (max - min) as f32;
+
+    let offset = match ore.distribution {
+        HeightDistribution::Uniform => random.gen::<f32>() * range,
+        HeightDistribution::Triangle => (random.gen::<f32>() + random.gen::<f32>()) / 2.0 * range,
+        HeightDistribution::Deep => random.gen::<f32>() * random.gen::<f32>() * range,
+    };
+
+    min + offset.round() as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::plugins::world::chunk::{components::BlockType, seeding::{chunk_rng, Stream}};
+    use crate::plugins::world::testing::load_registries;
+
+    use super::*;
+
+
+    const SEED: u64 = 12345;
+
+
+    fn gold(replaces: Vec<BlockType>) -> OreProperties {
+        OreProperties {
+            block: BlockType::from_name("ore_stone_gold").unwrap(),
+            vein_size: (8, 8),
+            veins_per_chunk: 1.0,
+            height: (40, 40),
+            distribution: HeightDistribution::Uniform,
+            replaces,
+        }
+    }
+
+
+    fn ore_cells(blocks: &ChunkData, ore: BlockType) -> Vec<(usize, usize, usize)> {
+        (0..CHUNK_WIDTH)
+            .flat_map(|x| (0..CHUNK_HEIGHT).flat_map(move |y| (0..CHUNK_WIDTH).map(move |z| (x, y, z))))
+            .filter(|(x, y, z)| blocks.get(*x, *y, *z) == ore)
+            .collect()
+    }
+
+
+    #[test]
+    fn veins_grow_from_their_start_within_their_size() {
+        load_registries();
+        let ore = gold(vec![BlockType::STONE]);
+
+        let mut blocks = ChunkData::new(BlockType::STONE);
+        add_vein(&mut chunk_rng(SEED, Stream::Ores, (0, 0)), &ore, &mut blocks);
+        let cells = ore_cells(&blocks, ore.block);
+
+        // A walk of 8 blocks stays within 7 steps of its start and may cross itself.
+        assert!(!cells.is_empty() && cells.len() <= 8);
+        assert!(cells.iter().all(|(_, y, _)| y.abs_diff(40) <= 7));
+
+        // The same seed grows the same vein.
+        let mut again = ChunkData::new(BlockType::STONE);
+        add_vein(&mut chunk_rng(SEED, Stream::Ores, (0, 0)), &ore, &mut again);
+        assert_eq!(ore_cells(&again, ore.block), cells);
+
+        // Blocks the ore can't replace are left alone.
+        let mut dirt = ChunkData::new(BlockType::DIRT);
+        add_vein(&mut chunk_rng(SEED, Stream::Ores, (0, 0)), &ore, &mut dirt);
+        assert!(ore_cells(&dirt, ore.block).is_empty());
+    }
+
+
+    #[test]
+    fn ores_keep_off_the_bedrock() {
+        load_registries();
+        let mut blocks = ChunkData::new(BlockType::STONE);
+        for (x, z) in (0..CHUNK_WIDTH).flat_map(|x| (0..CHUNK_WIDTH).map(move |z| (x, z))) {
+            blocks.set(x, 0, z, BlockType::BEDROCK);
+        }
+
+        generate_ores(&mut chunk_rng(SEED, Stream::Ores, (3, -2)), &mut blocks);
+
+        let ore_blocks: Vec<BlockType> = ores().iter().map(|ore| ore.block).collect();
+        let placed = blocks.blocks().filter(|block| ore_blocks.contains(block)).count();
+        assert!(placed > 0);
+        assert!((0..CHUNK_WIDTH).all(|x| (0..CHUNK_WIDTH).all(|z| blocks.get(x, 0, z) == BlockType::BEDROCK)));
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::plugins::world::{SeededPerlin, WorldMap, storage::WorldStorage, systems::load_or_generate_chunk, testing::{load_registries, TestDir}};
+use crate::plugins::world::chunk::{components::BlockType, data::ChunkData, pending::PendingBlocks, preset::WorldPreset};
+
+use super::{generate_chunk_data, GeneratedChunk};
+
+
+const SEED: u64 = 12345;
+
+
+// FNV-1a, which unlike the standard library's hasher is the same in every version of Rust.
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+
+fn blocks_hash(blocks: &ChunkData) -> u64 {
+    blocks.blocks().fold(0xCBF2_9CE4_8422_2325, |hash, block| fnv(hash, &block.raw().to_le_bytes()))
+}
+
+
+// Blocks of the chunk and the blocks it left for its neighbours.
+fn generated_hash(generated: &GeneratedChunk) -> u64 {
+    let mut hash = blocks_hash(&generated.blocks);
+
+    let mut pending: Vec<_> = generated.pending.iter()
+        .flat_map(|(chunk, blocks)| blocks.iter().map(move |(position, block)| (*chunk, *position, block.raw())))
+        .collect();
+    pending.sort();
+
+    for ((chunk_x, chunk_z), (x, y, z), block) in pending {
+        for value in [chunk_x as u64, chunk_z as u64, x as u64, y as u64, z as u64, block as u64] {
+            hash = fnv(hash, &value.to_le_bytes());
+        }
+    }
+
+    hash
+}
+
+
+// Loads or generates chunks one after another and puts them in the world the way
+// receive_generated_chunks does.
+fn generate_world(world_map: &mut WorldMap, dir: &Path, perlin: &SeededPerlin, order: &[(i32, i32)]) {
+    for &position in order {
+        world_map.insert_generated(load_or_generate_chunk(dir, perlin, position));
+    }
+}
+
+
+fn generate_region(perlin: &SeededPerlin, order: &[(i32, i32)]) -> HashMap<(i32, i32), ChunkData> {
+    let dir = TestDir::new("region");
+    let mut world_map = WorldMap::default();
+    generate_world(&mut world_map, dir.path(), perlin, order);
+
+    world_map.chunks
+}
+
+
+#[test]
+fn same_seed_generates_the_same_chunk() {
+    load_registries();
+
+    let first = generate_chunk_data(&SeededPerlin::new(SEED, WorldPreset::Default), (3, -7));
+    let second = generate_chunk_data(&SeededPerlin::new(SEED, WorldPreset::Default), (3, -7));
+
+    assert_eq!(generated_hash(&first), generated_hash(&second));
+}
+
+
+#[test]
+fn mirrored_chunks_differ() {
+    load_registries();
+    let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+
+    for (x, z) in [(1, 2), (5, -3), (-8, 4)] {
+        assert_ne!(generated_hash(&generate_chunk_data(&perlin, (x, z))), generated_hash(&generate_chunk_data(&perlin, (z, x))));
+    }
+}
+
+
+#[test]
+fn generation_order_does_not_matter() {
+    load_registries();
+    let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+
+    // A region of SEED with trees in it, around chunk (8, 0).
+    let mut order: Vec<(i32, i32)> = (4..12).flat_map(|x| (-4..4).map(move |z| (x, z))).collect();
+    let forward = generate_region(&perlin, &order);
+    order.reverse();
+    let backward = generate_region(&perlin, &order);
+    // Outwards from the middle, the way the game loads chunks around the player.
+    order.sort_by_key(|(x, z)| ((x - 8) * (x - 8) + z * z, *x, *z));
+    let outward = generate_region(&perlin, &order);
+
+    // Trees must cross chunk borders for the pending blocks to be tested.
+    let logs = forward.values().flat_map(|blocks| blocks.blocks()).filter(|block| *block == BlockType::WOOD_LOG).count();
+    assert!(logs > 0, "no trees in the tested region");
+
+    for position in order {
+        let hash = blocks_hash(&forward[&position]);
+        assert_eq!(hash, blocks_hash(&backward[&position]), "chunk {:?} depends on the order", position);
+        assert_eq!(hash, blocks_hash(&outward[&position]), "chunk {:?} depends on the order", position);
+    }
+}
+
+
+// Two chunks whose features write the same cell of a third leave the same block there,
+// whichever of the three is generated first, and neither replaces the third's own blocks.
+#[test]
+fn colliding_pending_blocks_do_not_depend_on_the_order() {
+    load_registries();
+    let target = (1, 0);
+    let chunk = |position: (i32, i32)| {
+        let mut blocks = ChunkData::new(BlockType::AIR);
+        let mut pending = HashMap::new();
+        match position {
+            (0, 0) => { pending.insert(target, PendingBlocks::from([((0, 5, 0), BlockType::WOOD_LOG), ((0, 6, 0), BlockType::WOOD_LOG)])); }
+            (2, 0) => { pending.insert(target, PendingBlocks::from([((0, 5, 0), BlockType::LEAVES), ((0, 6, 0), BlockType::LEAVES)])); }
+            _ => blocks.set(0, 6, 0, BlockType::STONE),
+        }
+        GeneratedChunk { position, blocks, pending, loaded: false, unsupported: vec![] }
+    };
+
+    let orders = [
+        [(0, 0), (2, 0), target], [(2, 0), (0, 0), target],
+        [(0, 0), target, (2, 0)], [(2, 0), target, (0, 0)],
+        [target, (0, 0), (2, 0)], [target, (2, 0), (0, 0)],
+    ];
+    let placed: Vec<BlockType> = orders.iter().map(|order| {
+        let mut world_map = WorldMap::default();
+        for &position in order {
+            world_map.insert_generated(chunk(position));
+        }
+
+        assert_eq!(world_map.chunks[&target].get(0, 6, 0), BlockType::STONE, "pending blocks replaced the chunk's own in order {:?}", order);
+        world_map.chunks[&target].get(0, 5, 0)
+    }).collect();
+
+    assert_ne!(placed[0], BlockType::AIR);
+    assert!(placed.iter().all(|block| *block == placed[0]), "the colliding block depends on the order: {:?}", placed);
+}
+
+
+// Blocks a neighbour's trees placed in a chunk are only handed over once, so they have to
+// survive the chunk being evicted and loaded again while the neighbour stays in memory.
+#[test]
+fn evicted_chunks_keep_their_neighbours_features() {
+    load_registries();
+    let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+    let dir = TestDir::new("eviction");
+    let storage = WorldStorage { dir: dir.path().to_path_buf(), level: None };
+
+    let order: Vec<(i32, i32)> = (4..12).flat_map(|x| (-4..4).map(move |z| (x, z))).collect();
+    let mut world_map = WorldMap::default();
+    generate_world(&mut world_map, storage.dir.as_path(), &perlin, &order);
+
+    // A chunk away from the edge of the region holding blocks of its neighbours' features.
+    let chunk = order.iter().copied()
+        .filter(|(x, z)| (5..11).contains(x) && (-3..3).contains(z))
+        .find(|chunk| blocks_hash(&world_map.chunks[chunk]) != blocks_hash(&generate_chunk_data(&perlin, *chunk).blocks))
+        .expect("no features cross a chunk border in the tested region");
+    let before = blocks_hash(&world_map.chunks[&chunk]);
+
+    world_map.evict(&[chunk], &storage).expect("[E] Could not evict the chunk!");
+    assert!(!world_map.chunks.contains_key(&chunk));
+
+    generate_world(&mut world_map, storage.dir.as_path(), &perlin, &[chunk]);
+    assert_eq!(blocks_hash(&world_map.chunks[&chunk]), before, "chunk {:?} lost blocks of its neighbours", chunk);
+}
+
+
+// Blocks waiting for a chunk that was never generated are kept while any chunk around it is
+// loaded, and dropped along with the last of them.
+#[test]
+fn evicting_a_region_drops_its_waiting_blocks() {
+    load_registries();
+    let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+    let dir = TestDir::new("waiting");
+    let storage = WorldStorage { dir: dir.path().to_path_buf(), level: None };
+
+    // Trees of the chunks around it reach into this one.
+    let missing = (10, -3);
+    let order: Vec<(i32, i32)> = (4..12).flat_map(|x| (-4..4).map(move |z| (x, z))).filter(|chunk| *chunk != missing).collect();
+    let mut world_map = WorldMap::default();
+    generate_world(&mut world_map, storage.dir.as_path(), &perlin, &order);
+    assert!(world_map.pending_blocks.contains_key(&missing), "no features reach into chunk {:?}", missing);
+
+    let last = (9, -4);
+    let (rest, others): (Vec<_>, Vec<_>) = order.iter().partition(|chunk| **chunk == last);
+    world_map.evict(&others, &storage).expect("[E] Could not evict the chunks!");
+    assert!(world_map.pending_blocks.contains_key(&missing));
+
+    world_map.evict(&rest, &storage).expect("[E] Could not evict the chunks!");
+    assert!(world_map.pending_blocks.is_empty());
+}
+
+
+// Meshes are built from copies of the chunks around theirs, so a mesh of a chunk next to an
+// edit has to be told apart from one built after it.
+#[test]
+fn edits_on_a_border_outdate_the_meshes_across_it() {
+    load_registries();
+    let mut world_map = WorldMap::default();
+    for chunk in [(0, 0), (1, 0)] {
+        world_map.chunks.insert(chunk, ChunkData::new(BlockType::STONE));
+    }
+
+    let before = world_map.revision((0, 0));
+    world_map.set_block((1, 0), 4, 50, 3, BlockType::AIR);
+    assert_eq!(world_map.revision((0, 0)), before, "an edit away from the border outdated the neighbour");
+
+    world_map.set_block((1, 0), 0, 50, 3, BlockType::AIR);
+    assert_ne!(world_map.revision((0, 0)), before, "an edit on the border kept the neighbour's mesh");
+    assert!(!world_map.chunk_revisions.contains_key(&(2, 0)));
+}
+
+
+// Hashes of chunks of a fixed seed. These only change when world generation is changed on
+// purpose, which changes the worlds players get from their seeds, so update them then.
+#[test]
+fn golden_chunk_hashes() {
+    load_registries();
+    let perlin = SeededPerlin::new(SEED, WorldPreset::Default);
+
+    let golden: [((i32, i32), u64); 4] = [
+        ((0, 0), 0x3692_9949_59CF_102D),
+        ((1, 2), 0xE2D9_04C4_E0F4_38C2),
+        ((2, 1), 0x754D_0D29_1340_C341),
+        ((-13, 40), 0x0C7C_B072_FCD8_07E8),
+    ];
+
+    for (position, hash) in golden {
+        assert_eq!(generated_hash(&generate_chunk_data(&perlin, position)), hash, "chunk {:?} changed", position);
+    }
+}
+
+
+#[test]
+fn superflat_worlds_are_their_layers() {
+    load_registries();
+
+    let preset = WorldPreset::parse("superflat", "bedrock, 2*stone, dirt, grass").expect("[E] Could not parse the layers!");
+    assert_eq!(preset.layers().as_deref(), Some("bedrock, 2*stone, dirt, grass"));
+
+    let generated = generate_chunk_data(&SeededPerlin::new(SEED, preset), (4, -9));
+    let column = [BlockType::BEDROCK, BlockType::STONE, BlockType::STONE, BlockType::DIRT, BlockType::GRASS, BlockType::AIR];
+
+    for (y, block) in column.into_iter().enumerate() {
+        for (x, z) in [(0, 0), (3, 5), (7, 7)] {
+            assert_eq!(generated.blocks.get(x, y, z), block, "wrong block at height {}", y);
+        }
+    }
+    assert!(generated.pending.is_empty());
+
+    assert!(WorldPreset::parse("superflat", "").is_err());
+    assert!(WorldPreset::parse("superflat", "0*dirt").is_err());
+    assert!(WorldPreset::parse("superflat", "3*lava").is_err());
+}
@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{GameGarbage, CHUNK_WIDTH, SECTION_HEIGHT, SECTION_COUNT};
+
+use super::{WorldMap, ChunkQueue, FluidQueue, ChunkMaterials};
+use super::chunk::{components::BlockType, data::ChunkData, light::{Position, block_at, locate, offset}, systems::generate_block_mesh};
+
+
+// Blocks that may have lost what held them up: their own position and the one above it.
+#[derive(Resource, Default)]
+pub struct GravityQueue {
+    pub scheduled: HashSet<Position>,
+    // Scheduled blocks of chunks that aren't built yet, held back until they are.
+    pub waiting: HashMap<(i32, i32), Vec<Position>>,
+}
+
+
+impl GravityQueue {
+    pub fn schedule(&mut self, position: Position) {
+        self.scheduled.insert(position);
+        self.scheduled.insert((position.0, position.1 + 1, position.2));
+    }
+
+
+    // The chunk was built, so its blocks have ground to land on.
+    pub fn release(&mut self, chunk_pos: (i32, i32)) {
+        if let Some(positions) = self.waiting.remove(&chunk_pos) {
+            self.scheduled.extend(positions);
+        }
+    }
+}
+
+
+// A block falling as a physics body, until it lands and turns back into a block.
+#[derive(Component)]
+pub struct FallingBlock {
+    pub block: BlockType,
+}
+
+
+// Blocks of a chunk that should fall as soon as they are in the world, such as sand the
+// terrain left hanging over a cave or the sea.
+pub fn unsupported_blocks(blocks: &ChunkData) -> Vec<(usize, usize, usize)> {
+    let mut unsupported = vec![];
+
+    for section in 0..SECTION_COUNT {
+        if !blocks.section(section).palette().iter().any(|block| block.falls()) {
+            continue;
+        }
+
+        for z in 0..CHUNK_WIDTH {
+            for y in (section * SECTION_HEIGHT).max(1)..(section + 1) * SECTION_HEIGHT {
+                for x in 0..CHUNK_WIDTH {
+                    if blocks.get(x, y, z).falls() && !blocks.get(x, y - 1, z).is_solid() {
+                        unsupported.push((x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    unsupported
+}
+
+
+pub fn drop_unsupported_blocks(
+    mut commands: Commands,
+    mut gravity_queue: ResMut<GravityQueue>,
+    mut world_map: ResMut<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut fluid_queue: ResMut<FluidQueue>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunk_materials: Res<ChunkMaterials>,
+) {
+    let scheduled: Vec<_> = gravity_queue.scheduled.drain().collect();
+
+    for position in scheduled {
+        let Some((chunk_pos, (x, y, z))) = locate(position) else {
+            continue;
+        };
+        let Some(blocks) = world_map.chunks.get(&chunk_pos) else {
+            continue;
+        };
+
+        // Wait until there is ground to land on.
+        if !world_map.chunk_entities.contains_key(&chunk_pos) {
+            gravity_queue.waiting.entry(chunk_pos).or_default().push(position);
+            continue;
+        }
+
+        let block = blocks.get(x, y, z);
+        if !block.falls() || y == 0 || blocks.get(x, y - 1, z).is_solid() {
+            continue;
+        }
+
+        let lit = world_map.set_block(chunk_pos, x, y, z, BlockType::AIR);
+        chunk_queue.mark_changed(&world_map, chunk_pos, x, y, z, lit);
+        fluid_queue.schedule_around(position);
+        gravity_queue.schedule(position);
+
+        commands.spawn((Name::new("FallingBlock"), MaterialMeshBundle {
+            mesh: meshes.add(generate_block_mesh(block)),
+            material: chunk_materials.chunk.clone(),
+            transform: Transform::from_xyz(position.0 as f32 + 0.5, position.1 as f32 + 0.5, position.2 as f32 + 0.5),
+            ..default()
+        }, GameGarbage))
+        .insert(FallingBlock { block })
+        .insert(RigidBody::Dynamic)
+        // Falling straight down keeps it in its column.
+        .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_X | LockedAxes::TRANSLATION_LOCKED_Z)
+        .insert(Collider::cuboid(0.45, 0.45, 0.45))
+        .insert(Velocity::zero())
+        .insert(Ccd::enabled());
+    }
+}
+
+
+pub fn land_falling_blocks(
+    mut commands: Commands,
+    falling_query: Query<(Entity, &Transform, &Velocity, &FallingBlock)>,
+    mut world_map: ResMut<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut fluid_queue: ResMut<FluidQueue>,
+    mut gravity_queue: ResMut<GravityQueue>,
+) {
+    for (entity, transform, velocity, falling) in falling_query.iter() {
+        let cell = transform.translation.floor();
+        if cell.y < 0.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let resting = velocity.linvel.y.abs() < 0.1;
+        let on_ground = world_map.block_at(cell - Vec3::Y).is_none_or(|below| below.is_solid());
+        if !resting || !on_ground {
+            continue;
+        }
+
+        commands.entity(entity).despawn();
+
+        let Some(position) = landing_position(&world_map, (cell.x as i32, cell.y as i32, cell.z as i32)) else {
+            continue;
+        };
+        let Some((chunk_pos, (x, y, z))) = locate(position) else {
+            continue;
+        };
+
+        let lit = world_map.set_block(chunk_pos, x, y, z, falling.block);
+        chunk_queue.mark_changed(&world_map, chunk_pos, x, y, z, lit);
+        fluid_queue.schedule_around(position);
+        gravity_queue.schedule(position);
+    }
+}
+
+
+// Where a block that came to rest at a position goes. Another block may have landed in the
+// same cell before the collider of the new one was built, so it stacks on top of whatever
+// is there. None if it runs out of the world or into a chunk that isn't loaded.
+pub fn landing_position(world_map: &WorldMap, position: Position) -> Option<Position> {
+    let mut position = position;
+    while block_at(&world_map.chunks, position)?.is_solid() {
+        position = offset(position, (0, 1, 0));
+    }
+
+    Some(position)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::world::testing::load_registries;
+
+
+    #[test]
+    fn blocks_landing_in_one_cell_stack_up() {
+        load_registries();
+        let mut world_map = WorldMap::default();
+        let mut blocks = ChunkData::new(BlockType::AIR);
+        for z in 0..CHUNK_WIDTH {
+            for x in 0..CHUNK_WIDTH {
+                blocks.set(x, 0, z, BlockType::STONE);
+            }
+        }
+        world_map.chunks.insert((0, 0), blocks);
+
+        // Both came to rest in the cell above the ground before either was meshed.
+        let rest = (3, 1, 4);
+        for expected in [rest, (3, 2, 4)] {
+            let position = landing_position(&world_map, rest).unwrap();
+            assert_eq!(position, expected);
+
+            let (chunk_pos, (x, y, z)) = locate(position).unwrap();
+            world_map.set_block(chunk_pos, x, y, z, BlockType::SAND);
+        }
+
+        assert_eq!(world_map.chunks[&(0, 0)].get(3, 1, 4), BlockType::SAND);
+        assert_eq!(world_map.chunks[&(0, 0)].get(3, 2, 4), BlockType::SAND);
+        assert_eq!(landing_position(&world_map, (3, 0, 4)), Some((3, 3, 4)));
+    }
+}
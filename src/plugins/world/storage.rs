@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, Seek, SeekFrom, BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use bevy::prelude::*;
+
+use crate::CHUNK_VOL;
+
+use super::chunk::{components::{BlockType, MAX_BLOCK_ID}, data::ChunkData, preset::WorldPreset};
+use super::time::DEFAULT_DAY_LENGTH;
+
+
+pub const SAVE_DIR: &str = "saves/world";
+
+// Chunks are grouped into regions of REGION_WIDTH x REGION_WIDTH chunks, one file per region.
+const REGION_WIDTH: i32 = 32;
+const REGION_MAGIC: &[u8; 4] = b"BCRG";
+const REGION_VERSION: u8 = 2;
+const REGION_HEADER_LEN: u64 = 5 + (REGION_WIDTH * REGION_WIDTH) as u64 * 8;
+
+type ChunkBlob = ((i32, i32), Vec<u8>);
+
+
+#[derive(Clone, Debug)]
+pub struct LevelData {
+    pub seed: u64,
+    pub preset: WorldPreset,
+    pub player_position: Vec3,
+    pub camera_rotation: Quat,
+    pub time_of_day: f32,
+    pub day_length: f32,
+}
+
+
+#[derive(Resource)]
+pub struct WorldStorage {
+    pub dir: PathBuf,
+    pub level: Option<LevelData>,
+}
+
+
+impl WorldStorage {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+
+        let level = match read_level(&dir.join("level.dat")) {
+            Ok(level) => Some(level),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                error!("[E] Could not read level data, starting a new world: {}", e);
+                None
+            }
+        };
+
+        WorldStorage { dir, level }
+    }
+
+
+    pub fn save_level(&mut self, level: LevelData) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(&self.dir.join("level.dat"), |w| write_level(w, &level))?;
+        self.level = Some(level);
+        Ok(())
+    }
+
+
+    // Moves the saved world to a directory named after the current time, so a new world can
+    // start in its place without losing the old one. Returns where it went, if there was one.
+    pub fn set_aside(&mut self) -> io::Result<Option<PathBuf>> {
+        if !self.dir.exists() {
+            self.level = None;
+            return Ok(None);
+        }
+
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("[E] SystemTime before UNIX EPOCH!").as_secs();
+        let mut name = self.dir.file_name().unwrap_or_default().to_os_string();
+        name.push(format!("-{}", time));
+        let moved = self.dir.with_file_name(name);
+
+        fs::rename(&self.dir, &moved)?;
+        self.level = None;
+        Ok(Some(moved))
+    }
+
+
+    // Writes the given chunks, merging them with whatever their region files already hold.
+    pub fn save_chunks<'a>(&self, chunks: impl Iterator<Item = ((i32, i32), &'a ChunkData)>) -> io::Result<()> {
+        let mut regions: HashMap<(i32, i32), Vec<ChunkBlob>> = HashMap::new();
+
+        for (chunk_pos, blocks) in chunks {
+            regions.entry(region_of(chunk_pos)).or_default().push((chunk_pos, encode_chunk(blocks)));
+        }
+
+        if regions.is_empty() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(self.dir.join("region"))?;
+
+        for (region, updates) in regions {
+            let path = region_path(&self.dir, region);
+
+            let mut slots = match File::open(&path).and_then(|file| read_region(&mut BufReader::new(file))) {
+                Ok(slots) => slots,
+                Err(e) if e.kind() == ErrorKind::NotFound => vec![None; (REGION_WIDTH * REGION_WIDTH) as usize],
+                // A region that can't be read is moved aside rather than keeping every other region from saving.
+                Err(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => {
+                    let bad = path.with_extension("region.bad");
+                    error!("[E] Could not read region {:?}, moving it to {:?}: {}", region, bad, e);
+                    fs::rename(&path, bad)?;
+                    vec![None; (REGION_WIDTH * REGION_WIDTH) as usize]
+                }
+                Err(e) => return Err(e),
+            };
+
+            for (chunk_pos, blob) in updates {
+                slots[slot_of(chunk_pos)] = Some(blob);
+            }
+
+            write_atomically(&path, |w| write_region(w, &slots))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// Takes the save directory rather than the resource so generation tasks can call it.
+pub fn load_chunk(dir: &Path, chunk_pos: (i32, i32)) -> io::Result<Option<ChunkData>> {
+    let path = region_path(dir, region_of(chunk_pos));
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    read_region_header(&mut file)?;
+    file.seek(SeekFrom::Start(5 + slot_of(chunk_pos) as u64 * 8))?;
+    let (offset, len) = (read_u32(&mut file)?, read_u32(&mut file)?);
+
+    if offset == 0 {
+        return Ok(None);
+    }
+
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let mut blob = vec![0; len as usize];
+    file.read_exact(&mut blob)?;
+
+    decode_chunk(&blob).map(Some)
+}
+
+
+fn region_path(dir: &Path, region: (i32, i32)) -> PathBuf {
+    dir.join("region").join(format!("r.{}.{}.region", region.0, region.1))
+}
+
+
+fn region_of(chunk_pos: (i32, i32)) -> (i32, i32) {
+    (chunk_pos.0.div_euclid(REGION_WIDTH), chunk_pos.1.div_euclid(REGION_WIDTH))
+}
+
+
+fn slot_of(chunk_pos: (i32, i32)) -> usize {
+    (chunk_pos.0.rem_euclid(REGION_WIDTH) + chunk_pos.1.rem_euclid(REGION_WIDTH) * REGION_WIDTH) as usize
+}
+
+
+fn read_region_header(r: &mut impl Read) -> io::Result<()> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    let mut version = [0; 1];
+    r.read_exact(&mut version)?;
+
+    if &magic != REGION_MAGIC || version[0] != REGION_VERSION {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a region file of a supported version"));
+    }
+
+    Ok(())
+}
+
+
+fn read_region(r: &mut impl Read) -> io::Result<Vec<Option<Vec<u8>>>> {
+    read_region_header(r)?;
+
+    let mut table = vec![];
+    for _ in 0 .. REGION_WIDTH * REGION_WIDTH {
+        table.push((read_u32(r)?, read_u32(r)?));
+    }
+
+    // Blobs are written back to back in slot order right after the header.
+    let mut slots = vec![];
+    for (offset, len) in table {
+        if offset == 0 {
+            slots.push(None);
+            continue;
+        }
+        let mut blob = vec![0; len as usize];
+        r.read_exact(&mut blob)?;
+        slots.push(Some(blob));
+    }
+
+    Ok(slots)
+}
+
+
+fn write_region(w: &mut impl Write, slots: &[Option<Vec<u8>>]) -> io::Result<()> {
+    w.write_all(REGION_MAGIC)?;
+    w.write_all(&[REGION_VERSION])?;
+
+    let mut offset = REGION_HEADER_LEN as u32;
+    for slot in slots {
+        match slot {
+            Some(blob) => {
+                w.write_all(&offset.to_le_bytes())?;
+                w.write_all(&(blob.len() as u32).to_le_bytes())?;
+                offset += blob.len() as u32;
+            }
+            None => {
+                w.write_all(&0u32.to_le_bytes())?;
+                w.write_all(&0u32.to_le_bytes())?;
+            }
+        }
+    }
+
+    for blob in slots.iter().flatten() {
+        w.write_all(blob)?;
+    }
+
+    Ok(())
+}
+
+
+// Chunks are stored as runs of (length: u16, block id and state: u16), since most of a column is air or stone.
+fn encode_chunk(blocks: &ChunkData) -> Vec<u8> {
+    let mut blob = vec![];
+    let mut run: Option<(BlockType, u16)> = None;
+
+    for block in blocks.blocks() {
+        run = match run {
+            Some((run_block, len)) if run_block == block && len < u16::MAX => Some((block, len + 1)),
+            Some((run_block, len)) => {
+                blob.extend(len.to_le_bytes());
+                blob.extend(run_block.raw().to_le_bytes());
+                Some((block, 1))
+            }
+            None => Some((block, 1)),
+        };
+    }
+
+    if let Some((run_block, len)) = run {
+        blob.extend(len.to_le_bytes());
+        blob.extend(run_block.raw().to_le_bytes());
+    }
+
+    blob
+}
+
+
+fn decode_chunk(blob: &[u8]) -> io::Result<ChunkData> {
+    let mut blocks = ChunkData::new(BlockType::AIR);
+    let mut index = 0;
+
+    for run in blob.chunks(4) {
+        let (len, id) = match run {
+            [a, b, c, d] => (u16::from_le_bytes([*a, *b]) as usize, u16::from_le_bytes([*c, *d])),
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "truncated chunk data")),
+        };
+
+        let block = BlockType::from_raw(id)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("unknown block id {}", id & MAX_BLOCK_ID)))?;
+
+        if index + len > CHUNK_VOL {
+            return Err(io::Error::new(ErrorKind::InvalidData, "chunk data overflows the chunk"));
+        }
+
+        for i in index .. index + len {
+            blocks.set_index(i, block);
+        }
+        index += len;
+    }
+
+    if index != CHUNK_VOL {
+        return Err(io::Error::new(ErrorKind::InvalidData, "chunk data does not fill the chunk"));
+    }
+
+    Ok(blocks)
+}
+
+
+fn read_level(path: &Path) -> io::Result<LevelData> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+
+    let mut seed = None;
+    // Worlds saved before there were presets are default worlds.
+    let mut preset = "default".to_string();
+    let mut layers = String::new();
+    let mut player_position = None;
+    let mut camera_rotation = None;
+    // Worlds saved before there was a day cycle start at noon.
+    let mut time_of_day = 0.5;
+    let mut day_length = DEFAULT_DAY_LENGTH;
+
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        match key.trim() {
+            "seed" => seed = value.trim().parse().ok(),
+            "preset" => preset = value.trim().to_string(),
+            "layers" => layers = value.trim().to_string(),
+            "player_position" => player_position = parse_floats::<3>(value).map(Vec3::from_array),
+            "camera_rotation" => camera_rotation = parse_floats::<4>(value).map(Quat::from_array),
+            "time_of_day" => time_of_day = value.trim().parse().unwrap_or(time_of_day),
+            "day_length" => day_length = value.trim().parse().unwrap_or(day_length),
+            _ => {}
+        }
+    }
+
+    let missing = |field| io::Error::new(ErrorKind::InvalidData, format!("level data is missing {}", field));
+
+    Ok(LevelData {
+        seed: seed.ok_or_else(|| missing("seed"))?,
+        preset: WorldPreset::parse(&preset, &layers).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+        player_position: player_position.ok_or_else(|| missing("player_position"))?,
+        camera_rotation: camera_rotation.ok_or_else(|| missing("camera_rotation"))?,
+        time_of_day,
+        day_length,
+    })
+}
+
+
+fn write_level(w: &mut impl Write, level: &LevelData) -> io::Result<()> {
+    let p = level.player_position;
+    let r = level.camera_rotation;
+
+    writeln!(w, "seed={}", level.seed)?;
+    writeln!(w, "preset={}", level.preset.name())?;
+    if let Some(layers) = level.preset.layers() {
+        writeln!(w, "layers={}", layers)?;
+    }
+    writeln!(w, "player_position={} {} {}", p.x, p.y, p.z)?;
+    writeln!(w, "camera_rotation={} {} {} {}", r.x, r.y, r.z, r.w)?;
+    writeln!(w, "time_of_day={}", level.time_of_day)?;
+    writeln!(w, "day_length={}", level.day_length)?;
+
+    Ok(())
+}
+
+
+fn parse_floats<const N: usize>(value: &str) -> Option<[f32; N]> {
+    let mut floats = [0.0; N];
+    let mut parts = value.split_whitespace();
+
+    for float in floats.iter_mut() {
+        *float = parts.next()?.parse().ok()?;
+    }
+
+    Some(floats)
+}
+
+
+// Write to a temporary file first so a crash mid-save never leaves a half written file behind.
+fn write_atomically(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+
+    let mut w = BufWriter::new(File::create(&tmp)?);
+    write(&mut w)?;
+    w.flush()?;
+    drop(w);
+
+    fs::rename(tmp, path)
+}
+
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::world::testing::{load_registries, TestDir};
+
+
+    // Runs of different lengths, and a block with state to check it is kept.
+    fn sample_chunk(seed: usize) -> ChunkData {
+        let mut blocks = ChunkData::new(BlockType::AIR);
+        for index in 0..CHUNK_VOL / 2 {
+            let block = match (index + seed) % 7 {
+                0 => BlockType::DIRT,
+                1 => BlockType::WATER.with_state(3),
+                _ => BlockType::STONE,
+            };
+            blocks.set_index(index, block);
+        }
+        blocks
+    }
+
+
+    fn same_blocks(a: &ChunkData, b: &ChunkData) -> bool {
+        a.blocks().eq(b.blocks())
+    }
+
+
+    #[test]
+    fn chunks_survive_encoding() {
+        load_registries();
+        let blocks = sample_chunk(0);
+        let blob = encode_chunk(&blocks);
+
+        assert!(same_blocks(&decode_chunk(&blob).unwrap(), &blocks));
+        assert!(same_blocks(&decode_chunk(&encode_chunk(&ChunkData::new(BlockType::AIR))).unwrap(), &ChunkData::new(BlockType::AIR)));
+
+        // Cut inside a run, and at a run that leaves the chunk short.
+        assert!(decode_chunk(&blob[..blob.len() - 1]).is_err());
+        assert!(decode_chunk(&blob[..blob.len() - 4]).is_err());
+    }
+
+
+    #[test]
+    fn regions_keep_every_chunk_written_to_them() {
+        load_registries();
+        let dir = TestDir::new("storage");
+        let storage = WorldStorage::open(dir.path());
+
+        // Two chunks of one region and one of the region next to it.
+        let first = [((0, 0), sample_chunk(0)), ((31, 5), sample_chunk(1)), ((-1, -1), sample_chunk(2))];
+        storage.save_chunks(first.iter().map(|(chunk, blocks)| (*chunk, blocks))).unwrap();
+
+        // Saving again replaces one chunk and keeps the others of its region.
+        let replaced = sample_chunk(3);
+        storage.save_chunks([((0, 0), &replaced)].into_iter()).unwrap();
+
+        assert!(same_blocks(&load_chunk(dir.path(), (0, 0)).unwrap().unwrap(), &replaced));
+        assert!(same_blocks(&load_chunk(dir.path(), (31, 5)).unwrap().unwrap(), &first[1].1));
+        assert!(same_blocks(&load_chunk(dir.path(), (-1, -1)).unwrap().unwrap(), &first[2].1));
+        assert!(load_chunk(dir.path(), (1, 1)).unwrap().is_none());
+        assert!(load_chunk(dir.path(), (64, 0)).unwrap().is_none());
+    }
+
+
+    #[test]
+    fn other_region_versions_are_refused() {
+        let dir = TestDir::new("version");
+        let path = region_path(dir.path(), (0, 0));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut region = vec![];
+        write_region(&mut region, &vec![None; (REGION_WIDTH * REGION_WIDTH) as usize]).unwrap();
+        region[4] = REGION_VERSION - 1;
+        fs::write(&path, region).unwrap();
+
+        assert_eq!(load_chunk(dir.path(), (0, 0)).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+
+    #[test]
+    fn unreadable_regions_are_moved_aside_when_saving() {
+        load_registries();
+        let dir = TestDir::new("unreadable");
+        let storage = WorldStorage::open(dir.path());
+        let path = region_path(dir.path(), (0, 0));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"BCRG\x01").unwrap();
+
+        let chunks = [((0, 0), sample_chunk(0)), ((-1, 0), sample_chunk(1))];
+        storage.save_chunks(chunks.iter().map(|(chunk, blocks)| (*chunk, blocks))).unwrap();
+
+        assert_eq!(fs::read(path.with_extension("region.bad")).unwrap(), b"BCRG\x01");
+        assert!(same_blocks(&load_chunk(dir.path(), (0, 0)).unwrap().unwrap(), &chunks[0].1));
+        assert!(same_blocks(&load_chunk(dir.path(), (-1, 0)).unwrap().unwrap(), &chunks[1].1));
+    }
+
+
+    #[test]
+    fn level_data_survives_a_save() {
+        let dir = TestDir::new("level");
+        let mut storage = WorldStorage::open(dir.path());
+        assert!(storage.level.is_none());
+
+        let level = LevelData {
+            seed: u64::MAX,
+            preset: WorldPreset::Islands,
+            player_position: Vec3::new(1.5, 80.0, -3.25),
+            camera_rotation: Quat::from_rotation_y(1.0),
+            time_of_day: 0.75,
+            day_length: 600.0,
+        };
+        storage.save_level(level.clone()).unwrap();
+
+        let loaded = WorldStorage::open(dir.path()).level.unwrap();
+        assert_eq!(loaded.seed, level.seed);
+        assert_eq!(loaded.preset, level.preset);
+        assert_eq!(loaded.player_position, level.player_position);
+        assert_eq!(loaded.camera_rotation, level.camera_rotation);
+        assert_eq!(loaded.time_of_day, level.time_of_day);
+        assert_eq!(loaded.day_length, level.day_length);
+    }
+}
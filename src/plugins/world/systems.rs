@@ -1,35 +1,174 @@
-use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, block_on, futures_lite::future}};
+use rand::Rng;
 
-use crate::{RENDER_DISTANCE, CHUNK_WIDTH, plugins::player::components::Player};
+use crate::{RENDER_DISTANCE, CHUNK_WIDTH, SECTION_HEIGHT, SECTION_COUNT, plugins::player::components::{Player, PlayerCamera}};
 
-use super::{chunk::systems::{generate_chunk_data, build_chunk}, WorldMap, SeededPerlin, ChunkQueue};
+use super::{chunk::systems::{generate_chunk_data, snapshot_chunks, mesh_chunk_sections, spawn_chunk_meshes, GeneratedChunk}, chunk::light::{world_position, light_chunk, locate}, chunk::fluid::next_water, chunk::random_ticks::{random_tick, ticks_randomly}, chunk::biome::blend_at, WorldMap, SeededPerlin, ChunkQueue, ChunkTasks, ChunkMaterials, MeshingSettings, FluidQueue, RandomTicks};
+use super::storage::{WorldStorage, LevelData, load_chunk};
+use super::time::WorldTime;
+use super::gravity::{GravityQueue, unsupported_blocks};
+
+
+// Upper bound on chunks being loaded or generated at once, so spawning or crossing
+// a chunk border doesn't flood the task pool with work that may soon be out of range.
+const MAX_GENERATION_TASKS: usize = 32;
+
+pub const FLUID_TICK: f32 = 0.25;
+const MAX_FLUID_UPDATES: usize = 1024;
+
+pub const RANDOM_TICK: f32 = 0.1;
+// Blocks picked per section on every random tick, in chunks this close to the player.
+const RANDOM_TICKS_PER_SECTION: usize = 3;
+const RANDOM_TICK_DISTANCE: i32 = 16;
 
 
 pub fn generate_chunks_from_player_movement(
     player_query: Query<&Transform, With<Player>>,
-    mut world_map: ResMut<WorldMap>,
+    world_map: Res<WorldMap>,
     perlin: Res<SeededPerlin>,
     mut chunk_queue: ResMut<ChunkQueue>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    storage: Res<WorldStorage>,
 ) {
     let player_transform = player_query.single();
     let (chunk_x, chunk_z) = ((player_transform.translation.x / CHUNK_WIDTH as f32).round() as i32, (player_transform.translation.z / CHUNK_WIDTH as f32).round() as i32);
 
     let render_distance = RENDER_DISTANCE;
 
+    // Dropping a task cancels it.
+    chunk_tasks.generating.retain(|position, _| {
+        (position.0 - chunk_x).abs() <= render_distance + 1 && (position.1 - chunk_z).abs() <= render_distance + 1
+    });
+
+    let mut missing = vec![];
     for x in -(render_distance + 1)..(render_distance + 1) {
         for z in -(render_distance + 1)..(render_distance + 1) {
-            if !world_map.chunks.contains_key(&(chunk_x + x, chunk_z + z)) {
-                generate_chunk_data(&perlin, (chunk_x + x, chunk_z + z), &mut world_map);
+            let chunk = (chunk_x + x, chunk_z + z);
+            if !world_map.chunks.contains_key(&chunk) && !chunk_tasks.generating.contains_key(&chunk) {
+                missing.push(chunk);
             }
         }
     }
+    missing.sort_by_key(|chunk| (chunk.0 - chunk_x).pow(2) + (chunk.1 - chunk_z).pow(2));
+
+    let task_pool = AsyncComputeTaskPool::get();
+    let free_slots = MAX_GENERATION_TASKS.saturating_sub(chunk_tasks.generating.len());
+
+    for chunk in missing.into_iter().take(free_slots) {
+        let perlin = perlin.clone();
+        let dir = storage.dir.clone();
+        let task = task_pool.spawn(async move { load_or_generate_chunk(&dir, &perlin, chunk) });
+        chunk_tasks.generating.insert(chunk, task);
+    }
 
     for x in -render_distance..render_distance {
         for z in -render_distance..render_distance {
-            if !chunk_queue.queue.contains(&(chunk_x + x, chunk_z + z)) && !world_map.chunk_entities.contains_key(&(chunk_x + x, chunk_z + z)) {
-                enque_chunk(&mut chunk_queue, (chunk_x + x, chunk_z + z));
+            let chunk = (chunk_x + x, chunk_z + z);
+            if !chunk_queue.dirty.contains_key(&chunk)
+            && !world_map.chunk_entities.contains_key(&chunk)
+            && !chunk_tasks.meshing.contains_key(&chunk)
+            && neighbourhood_loaded(&world_map, chunk) {
+                chunk_queue.dirty.insert(chunk, (0..SECTION_COUNT).collect());
+            }
+        }
+    }
+}
+
+
+pub fn receive_generated_chunks(
+    mut world_map: ResMut<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut gravity_queue: ResMut<GravityQueue>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+) {
+    chunk_tasks.generating.retain(|_, task| {
+        let Some(generated) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+
+        let position = generated.position;
+        for &(x, y, z) in &generated.unsupported {
+            gravity_queue.schedule(world_position(position, x, y, z));
+        }
+
+        // Built chunks are remeshed where blocks or light changed. Chunks that aren't
+        // built yet will see them when all of their sections are meshed.
+        for (chunk, section) in world_map.insert_generated(generated) {
+            if world_map.chunk_entities.contains_key(&chunk) {
+                chunk_queue.mark_dirty(chunk, section);
             }
         }
+
+        false
+    });
+}
+
+
+// Meshing looks one block past the chunk border, diagonals included for AO.
+fn neighbourhood_loaded(world_map: &WorldMap, chunk: (i32, i32)) -> bool {
+    (-1..=1).all(|x| (-1..=1).all(|z| world_map.chunks.contains_key(&(chunk.0 + x, chunk.1 + z))))
+}
+
+
+pub fn load_or_generate_chunk(
+    dir: &Path,
+    perlin: &SeededPerlin,
+    chunk_pos: (i32, i32),
+) -> GeneratedChunk {
+    let generated = match load_chunk(dir, chunk_pos) {
+        // The blocks come from disk, but the chunk is still generated for what its
+        // features place in neighbours that may not have been saved.
+        Ok(Some(blocks)) => GeneratedChunk { blocks, loaded: true, ..generate_chunk_data(perlin, chunk_pos) },
+        Ok(None) => generate_chunk_data(perlin, chunk_pos),
+        Err(e) => {
+            error!("[E] Could not load chunk {:?}, regenerating it: {}", chunk_pos, e);
+            generate_chunk_data(perlin, chunk_pos)
+        }
+    };
+
+    // Light isn't saved, it is worked out again for every chunk that comes in.
+    GeneratedChunk {
+        unsupported: unsupported_blocks(&generated.blocks),
+        blocks: light_chunk(chunk_pos, generated.blocks),
+        ..generated
+    }
+}
+
+
+pub fn save_world(
+    player_query: Query<&Transform, With<Player>>,
+    camera_query: Query<&Transform, (With<PlayerCamera>, Without<Player>)>,
+    mut world_map: ResMut<WorldMap>,
+    mut storage: ResMut<WorldStorage>,
+    perlin: Res<SeededPerlin>,
+    world_time: Res<WorldTime>,
+) {
+    let modified = world_map.modified_chunks.iter()
+        .filter_map(|pos| world_map.chunks.get(pos).map(|blocks| (*pos, blocks)));
+
+    if let Err(e) = storage.save_chunks(modified) {
+        error!("[E] Could not save chunks: {}", e);
+        return;
+    }
+    world_map.modified_chunks.clear();
+
+    let (Ok(player_transform), Ok(camera_transform)) = (player_query.get_single(), camera_query.get_single()) else {
+        return;
+    };
+
+    let level = LevelData {
+        seed: perlin.seed,
+        preset: perlin.preset.clone(),
+        player_position: player_transform.translation,
+        camera_rotation: camera_transform.rotation,
+        time_of_day: world_time.time_of_day,
+        day_length: world_time.day_length,
+    };
+
+    if let Err(e) = storage.save_level(level) {
+        error!("[E] Could not save level data: {}", e);
     }
 }
 
@@ -38,75 +177,346 @@ pub fn unload_far_chunks(
     mut commands: Commands,
     player_query: Query<&Transform, With<Player>>,
     mut world_map: ResMut<WorldMap>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
 ) {
     let player_transform = player_query.single();
     let (chunk_x, chunk_z) = ((player_transform.translation.x / CHUNK_WIDTH as f32).round() as i32, (player_transform.translation.z / CHUNK_WIDTH as f32).round() as i32);
+
+    chunk_tasks.meshing.retain(|chunk_position, _| {
+        (chunk_x - chunk_position.0).abs() <= RENDER_DISTANCE && (chunk_z - chunk_position.1).abs() <= RENDER_DISTANCE
+    });
     
     for chunk in world_map.chunk_entities.clone().iter() {
-        let chunk_position = chunk.0.clone();
+        let chunk_position = *chunk.0;
 
         if (chunk_x - chunk_position.0).abs() > RENDER_DISTANCE ||  (chunk_z - chunk_position.1).abs() > RENDER_DISTANCE {
-            commands.entity(*chunk.1).despawn();
+            for entity in chunk.1.iter().flatten() {
+                commands.entity(*entity).despawn();
+            }
             world_map.chunk_entities.remove(&chunk_position);
         }
     }
 
     for chunk in world_map.water_chunk_entities.clone().iter() {
-        let chunk_position = chunk.0.clone();
+        let chunk_position = *chunk.0;
 
         if (chunk_x - chunk_position.0).abs() > RENDER_DISTANCE ||  (chunk_z - chunk_position.1).abs() > RENDER_DISTANCE {
-            commands.entity(*chunk.1).despawn();
+            for entity in chunk.1.iter().flatten() {
+                commands.entity(*entity).despawn();
+            }
             world_map.water_chunk_entities.remove(&chunk_position);
         }
     }
 }
 
 
-pub fn enque_chunk(chunk_queue: &mut ResMut<ChunkQueue>, position: (i32,i32)) {
-    chunk_queue.queue.push(position);
+// Chunk data kept in memory. Needs to stay well above the (2 * RENDER_DISTANCE + 2)^2
+// chunks loaded around the player, or chunks would be evicted just to be loaded again.
+const MAX_CACHED_CHUNKS: usize = 4096;
+
+
+// Drop chunk data once the cache is full, least recently used and farthest away first.
+// Modified chunks are written to disk before they are dropped, and loaded from there
+// again when the player comes back.
+pub fn evict_chunk_data(
+    player_query: Query<&Transform, With<Player>>,
+    mut world_map: ResMut<WorldMap>,
+    mut gravity_queue: ResMut<GravityQueue>,
+    storage: Res<WorldStorage>,
+    time: Res<Time>,
+) {
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let (chunk_x, chunk_z) = ((player_transform.translation.x / CHUNK_WIDTH as f32).round() as i32, (player_transform.translation.z / CHUNK_WIDTH as f32).round() as i32);
+    let load_distance = RENDER_DISTANCE + 1;
+    let now = time.elapsed_seconds_f64();
+
+    // Everything in loading range is in use.
+    for x in -load_distance..=load_distance {
+        for z in -load_distance..=load_distance {
+            let chunk = (chunk_x + x, chunk_z + z);
+            if world_map.chunks.contains_key(&chunk) {
+                world_map.last_access.insert(chunk, now);
+            }
+        }
+    }
+
+    if world_map.chunks.len() <= MAX_CACHED_CHUNKS {
+        return;
+    }
+
+    let distance = |chunk: &(i32, i32)| (chunk.0 - chunk_x).pow(2) + (chunk.1 - chunk_z).pow(2);
+    let last_access = |chunk: &(i32, i32)| world_map.last_access.get(chunk).copied().unwrap_or_default();
+
+    let mut evicted: Vec<(i32, i32)> = world_map.chunks.keys()
+        .filter(|chunk| (chunk.0 - chunk_x).abs() > load_distance || (chunk.1 - chunk_z).abs() > load_distance)
+        .filter(|chunk| !world_map.chunk_entities.contains_key(chunk))
+        .copied()
+        .collect();
+    evicted.sort_by(|a, b| last_access(a).total_cmp(&last_access(b)).then(distance(b).cmp(&distance(a))));
+    evicted.truncate(world_map.chunks.len() - MAX_CACHED_CHUNKS);
+
+    if let Err(e) = world_map.evict(&evicted, &storage) {
+        error!("[E] Could not save chunks, keeping them in memory: {}", e);
+        return;
+    }
+
+    // Falling blocks are found again when the chunks are loaded back.
+    for chunk in &evicted {
+        gravity_queue.waiting.remove(chunk);
+    }
 }
 
 
+// Upper bound on chunks being meshed at once.
+const MAX_MESHING_TASKS: usize = 16;
+
+
 pub fn deque_chunks(
+    world_map: Res<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    player_query: Query<&Transform, With<Player>>,
+    meshing_settings: Res<MeshingSettings>,
+    perlin: Res<SeededPerlin>,
+) {
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let position = ((player_transform.translation.x / CHUNK_WIDTH as f32).floor() as i32, (player_transform.translation.z / CHUNK_WIDTH as f32).floor() as i32);
+
+    let task_pool = AsyncComputeTaskPool::get();
+
+    while chunk_tasks.meshing.len() < MAX_MESHING_TASKS {
+        // A chunk already being meshed waits in the queue until its current task is done.
+        let Some(chunk) = get_closest_chunk_from_queue(&chunk_queue.dirty, position, |chunk| !chunk_tasks.meshing.contains_key(chunk)) else {
+            break;
+        };
+
+        // Every queued section of the chosen chunk is meshed in one go.
+        let sections: Vec<usize> = chunk_queue.dirty.remove(&chunk).unwrap_or_default().into_iter().collect();
+
+        if !world_map.chunks.contains_key(&chunk) {
+            continue;
+        }
+
+        let snapshot = snapshot_chunks(&world_map, chunk);
+        let revision = world_map.revision(chunk);
+        let greedy = meshing_settings.greedy;
+        let perlin = perlin.clone();
+
+        let task = task_pool.spawn(async move { mesh_chunk_sections(&snapshot, &perlin, chunk, revision, &sections, greedy) });
+        chunk_tasks.meshing.insert(chunk, task);
+    }
+}
+
+
+pub fn receive_chunk_meshes(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     mut world_map: ResMut<WorldMap>,
     mut chunk_queue: ResMut<ChunkQueue>,
-    asset_server: Res<AssetServer>,
-    player_query: Query<&Transform, With<Player>>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut gravity_queue: ResMut<GravityQueue>,
+    chunk_materials: Res<ChunkMaterials>,
 ) {
-    if chunk_queue.queue.len() > 0 && chunk_queue.is_next_ready { 
-        chunk_queue.is_next_ready = false;
-        if let Ok(player_transform) = player_query.get_single() {
-            let position = ((player_transform.translation.x / CHUNK_WIDTH as f32).floor() as i32, (player_transform.translation.z / CHUNK_WIDTH as f32).floor() as i32);
-            let closest_index = get_closest_chunk_from_queue(&chunk_queue.queue, position);
-            let chunk = chunk_queue.queue[closest_index];
+    chunk_tasks.meshing.retain(|_, task| {
+        let Some(chunk_meshes) = block_on(future::poll_once(task)) else {
+            return true;
+        };
 
-            if world_map.chunks.contains_key(&chunk) {
-                chunk_queue.queue.remove(closest_index);
-                chunk_queue.is_next_ready = build_chunk(&mut commands, &mut world_map, &mut meshes, &mut materials, asset_server, chunk);
+        // The chunk was edited while it was being meshed, so mesh the same sections again.
+        if chunk_meshes.revision != world_map.revision(chunk_meshes.position) {
+            for section_meshes in chunk_meshes.sections.iter() {
+                chunk_queue.mark_dirty(chunk_meshes.position, section_meshes.section);
             }
+            return false;
         }
-    }
+
+        // Chunks get their entities here, which is what falling blocks in them wait for.
+        let position = chunk_meshes.position;
+        spawn_chunk_meshes(&mut commands, &mut world_map, &mut meshes, &chunk_materials, chunk_meshes);
+        gravity_queue.release(position);
+
+        false
+    });
 }
 
 
 fn get_closest_chunk_from_queue(
-    queue: &Vec<(i32, i32)>,
+    queue: &HashMap<(i32, i32), HashSet<usize>>,
     position: (i32,i32),
-) -> usize {
-    let mut closest = (100, 100);
-    let mut index = 0;
-
-    for i in 0..queue.len() {
-        let distance_chunk = (((queue[i].0 - position.0).pow(2) + (queue[i].1 - position.1).pow(2)) as f32).sqrt();
-        let distance_closest = (((closest.0 - position.0).pow(2) + (closest.1 - position.1).pow(2)) as f32).sqrt();
-        if distance_chunk < distance_closest {
-            closest = queue[i];
-            index = i;
+    available: impl Fn(&(i32, i32)) -> bool,
+) -> Option<(i32, i32)> {
+    queue.keys()
+        .copied()
+        .filter(|chunk| available(chunk))
+        .min_by_key(|chunk| (chunk.0 - position.0).pow(2) + (chunk.1 - position.1).pow(2))
+}
+
+
+// F3 logs the vertex count of the loaded chunk meshes and switches between the greedy
+// and the naive mesher, remeshing every built chunk with the other one.
+pub fn toggle_greedy_meshing(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut meshing_settings: ResMut<MeshingSettings>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    world_map: Res<WorldMap>,
+    mesh_query: Query<&Handle<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    if !keyboard.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    let vertices: usize = world_map.chunk_entities.values()
+        .chain(world_map.water_chunk_entities.values())
+        .flat_map(|entities| entities.iter().flatten())
+        .filter_map(|entity| mesh_query.get(*entity).ok())
+        .filter_map(|handle| meshes.get(handle))
+        .map(|mesh| mesh.count_vertices())
+        .sum();
+
+    let mode = |greedy: bool| if greedy { "greedy" } else { "naive" };
+    info!("[I] {} meshing: {} vertices in {} chunks", mode(meshing_settings.greedy), vertices, world_map.chunk_entities.len());
+
+    meshing_settings.greedy = !meshing_settings.greedy;
+    info!("[I] Switching to {} meshing", mode(meshing_settings.greedy));
+
+    for chunk in world_map.chunk_entities.keys() {
+        chunk_queue.dirty.insert(*chunk, (0..SECTION_COUNT).collect());
+    }
+}
+
+
+// F4 logs the biomes the player is standing in, blended the same way the terrain is.
+pub fn log_player_biome(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    perlin: Res<SeededPerlin>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    if !keyboard.just_pressed(KeyCode::F4) {
+        return;
+    }
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+
+    let (x, z) = (player_transform.translation.x.floor() as i32, player_transform.translation.z.floor() as i32);
+    let shares: Vec<String> = blend_at(&perlin, x, z).shares().iter()
+        .map(|(biome, share)| format!("{} {:.0}%", biome.name(), share * 100.0))
+        .collect();
+    info!("[I] Biome at {}, {} is {}", x, z, shares.join(", "));
+}
+
+
+// Player edits skip the task pool: the edited sections of built chunks are meshed right
+// away, so the change shows up in the same frame.
+pub fn rebuild_edited_chunks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut world_map: ResMut<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    chunk_materials: Res<ChunkMaterials>,
+    meshing_settings: Res<MeshingSettings>,
+    perlin: Res<SeededPerlin>,
+) {
+    for (chunk, sections) in std::mem::take(&mut chunk_queue.edited) {
+        // Chunks that aren't built yet get all of their sections meshed when they are.
+        if !world_map.chunk_entities.contains_key(&chunk) || !neighbourhood_loaded(&world_map, chunk) {
+            continue;
+        }
+
+        let sections: Vec<usize> = sections.into_iter().collect();
+        let snapshot = snapshot_chunks(&world_map, chunk);
+        let revision = world_map.revision(chunk);
+
+        let chunk_meshes = mesh_chunk_sections(&snapshot, &perlin, chunk, revision, &sections, meshing_settings.greedy);
+        spawn_chunk_meshes(&mut commands, &mut world_map, &mut meshes, &chunk_materials, chunk_meshes);
+    }
+}
+
+
+// Let scheduled water flow one step. Every block decides what it turns into before any of
+// them change, so the order they are visited in doesn't matter.
+pub fn flow_water(
+    time: Res<Time>,
+    mut fluid_queue: ResMut<FluidQueue>,
+    mut world_map: ResMut<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+) {
+    if !fluid_queue.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let mut scheduled: Vec<_> = fluid_queue.scheduled.drain().collect();
+    if scheduled.len() > MAX_FLUID_UPDATES {
+        fluid_queue.scheduled.extend(scheduled.drain(MAX_FLUID_UPDATES..));
+    }
+
+    let changes: Vec<_> = scheduled.into_iter()
+        .filter_map(|position| next_water(&world_map.chunks, position).map(|block| (position, block)))
+        .collect();
+
+    for (position, block) in changes {
+        let Some((chunk_pos, (x, y, z))) = locate(position) else {
+            continue;
+        };
+
+        let lit = world_map.set_block(chunk_pos, x, y, z, block);
+        chunk_queue.mark_changed(&world_map, chunk_pos, x, y, z, lit);
+        fluid_queue.schedule_around(position);
+    }
+}
+
+
+pub fn random_tick_sections(
+    time: Res<Time>,
+    player_query: Query<&Transform, With<Player>>,
+    mut random_ticks: ResMut<RandomTicks>,
+    mut world_map: ResMut<WorldMap>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut fluid_queue: ResMut<FluidQueue>,
+    mut gravity_queue: ResMut<GravityQueue>,
+) {
+    if !random_ticks.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let (chunk_x, chunk_z) = ((player_transform.translation.x / CHUNK_WIDTH as f32).round() as i32, (player_transform.translation.z / CHUNK_WIDTH as f32).round() as i32);
+
+    let random = &mut random_ticks.random;
+    let mut changes = vec![];
+
+    for (&chunk_pos, blocks) in world_map.chunks.iter() {
+        if (chunk_pos.0 - chunk_x).abs() > RANDOM_TICK_DISTANCE || (chunk_pos.1 - chunk_z).abs() > RANDOM_TICK_DISTANCE {
+            continue;
+        }
+
+        for section in 0..SECTION_COUNT {
+            if !blocks.section(section).palette().iter().any(|block| ticks_randomly(*block)) {
+                continue;
+            }
+
+            for _ in 0..RANDOM_TICKS_PER_SECTION {
+                let (x, y, z) = (random.gen_range(0..CHUNK_WIDTH), section * SECTION_HEIGHT + random.gen_range(0..SECTION_HEIGHT), random.gen_range(0..CHUNK_WIDTH));
+                changes.extend(random_tick(&world_map.chunks, world_position(chunk_pos, x, y, z), random));
+            }
         }
     }
 
-    index
-}
\ No newline at end of file
+    for (position, block) in changes {
+        let Some((chunk_pos, (x, y, z))) = locate(position) else {
+            continue;
+        };
+
+        let lit = world_map.set_block(chunk_pos, x, y, z, block);
+        chunk_queue.mark_changed(&world_map, chunk_pos, x, y, z, lit);
+        fluid_queue.schedule_around(position);
+        gravity_queue.schedule(position);
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use super::asset_path;
+use super::chunk::{atlas::{build_block_atlas, TEXTURES_DIR}, biome::{load_biome_registry, BIOMES_PATH}, ore::{load_ore_registry, ORES_PATH}, registry::{load_block_registry, BLOCKS_PATH}};
+
+
+// The registries can only be set once, so every test that needs them shares this.
+pub fn load_registries() {
+    static LOAD: Once = Once::new();
+
+    LOAD.call_once(|| {
+        let atlas = build_block_atlas(asset_path(TEXTURES_DIR)).expect("[E] Could not build the block texture atlas!");
+        load_block_registry(asset_path(BLOCKS_PATH), &atlas.tiles).expect("[E] Could not load the block registry!");
+        load_biome_registry(asset_path(BIOMES_PATH)).expect("[E] Could not load the biome registry!");
+        load_ore_registry(asset_path(ORES_PATH)).expect("[E] Could not load the ore registry!");
+    });
+}
+
+
+// Save directory of a test, removed again when the test is done with it.
+pub struct TestDir(PathBuf);
+
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("budgetcraft-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        TestDir(dir)
+    }
+
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+
+use super::{ChunkMaterials, WATER_COLOR, chunk::material::ChunkMaterial, storage::WorldStorage};
+
+
+pub const DEFAULT_DAY_LENGTH: f32 = 1200.0;
+const MIN_DAY_LENGTH: f32 = 60.0;
+const MAX_DAY_LENGTH: f32 = 24000.0;
+// New worlds start in the morning.
+const START_TIME: f32 = 0.3;
+// Daylight is rounded to this many steps, so the materials only change now and then.
+const DAYLIGHT_STEPS: f32 = 64.0;
+
+// Sky colour and how much of the sky light reaches the world through the day, where
+// 0.0 is midnight, 0.25 sunrise, 0.5 noon and 0.75 sunset. Values in between are blended.
+const SKY_KEYS: [(f32, [f32; 3], f32); 8] = [
+    (0.0, [0.04, 0.06, 0.15], 0.2),
+    (0.2, [0.04, 0.06, 0.15], 0.2),
+    (0.25, [0.96, 0.64, 0.38], 0.6),
+    (0.3, [0.56, 0.83, 1.0], 1.0),
+    (0.7, [0.56, 0.83, 1.0], 1.0),
+    (0.75, [0.99, 0.49, 0.31], 0.6),
+    (0.8, [0.04, 0.06, 0.15], 0.2),
+    (1.0, [0.04, 0.06, 0.15], 0.2),
+];
+
+
+#[derive(Resource, Debug)]
+pub struct WorldTime {
+    // Fraction of the current day that has passed.
+    pub time_of_day: f32,
+    // Length of a full day in seconds.
+    pub day_length: f32,
+}
+
+
+impl WorldTime {
+    fn sky(&self) -> (Color, f32) {
+        let t = self.time_of_day;
+        let next = SKY_KEYS.iter().position(|(key, _, _)| *key > t).unwrap_or(SKY_KEYS.len() - 1).max(1);
+        let ((from, from_color, from_light), (to, to_color, to_light)) = (SKY_KEYS[next - 1], SKY_KEYS[next]);
+
+        let f = ((t - from) / (to - from)).clamp(0.0, 1.0);
+        let color = Vec3::from_array(from_color).lerp(Vec3::from_array(to_color), f);
+
+        (Color::rgb(color.x, color.y, color.z), from_light + (to_light - from_light) * f)
+    }
+}
+
+
+pub fn setup_world_time(mut commands: Commands, storage: Res<WorldStorage>) {
+    let world_time = match &storage.level {
+        Some(level) => WorldTime { time_of_day: level.time_of_day, day_length: level.day_length },
+        None => WorldTime { time_of_day: START_TIME, day_length: DEFAULT_DAY_LENGTH },
+    };
+
+    commands.insert_resource(world_time);
+}
+
+
+pub fn advance_world_time(time: Res<Time>, mut world_time: ResMut<WorldTime>) {
+    world_time.time_of_day = (world_time.time_of_day + time.delta_seconds() / world_time.day_length).fract();
+}
+
+
+pub fn update_sky(
+    world_time: Res<WorldTime>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut clear_color: ResMut<ClearColor>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let (color, daylight) = world_time.sky();
+    clear_color.0 = color;
+
+    // Every write to a material uploads it to the GPU again, so leave them alone until daylight changes a step.
+    let daylight = (daylight * DAYLIGHT_STEPS).round() / DAYLIGHT_STEPS;
+    if materials.get(&chunk_materials.chunk).is_some_and(|material| material.daylight == daylight) {
+        return;
+    }
+
+    if let Some(material) = materials.get_mut(&chunk_materials.chunk) {
+        material.daylight = daylight;
+    }
+
+    // Water has its light baked into its vertex colours, so the sky only darkens its base colour.
+    if let Some(material) = standard_materials.get_mut(&chunk_materials.water) {
+        let [r, g, b, a] = WATER_COLOR;
+        material.base_color = Color::rgba(r * daylight, g * daylight, b * daylight, a);
+    }
+}
+
+
+// F5 halves the length of a day, F6 doubles it.
+pub fn change_day_length(keyboard: Res<ButtonInput<KeyCode>>, mut world_time: ResMut<WorldTime>) {
+    let factor = if keyboard.just_pressed(KeyCode::F5) {
+        0.5
+    }
+    else if keyboard.just_pressed(KeyCode::F6) {
+        2.0
+    }
+    else {
+        return;
+    };
+
+    world_time.day_length = (world_time.day_length * factor).clamp(MIN_DAY_LENGTH, MAX_DAY_LENGTH);
+    info!("[I] Day length is now {} seconds", world_time.day_length);
+}